@@ -0,0 +1,74 @@
+use bson::{doc, Bson, Document};
+use crate::AppState;
+use crate::db::{find_record, get_record};
+use crate::error::AppError;
+use crate::structures::{Claims, PostStatus, Resource, User, Visibility};
+
+// Скрытый администратором пост (post hide) недоступен никому, в том числе по подписанной ссылке
+pub fn ensure_not_hidden(post: &Resource) -> Result<(), AppError> {
+    if post.hidden {
+        return Err(AppError::not_found("post", post.id));
+    }
+    Ok(())
+}
+
+// Проверяет, может ли пользователь (None — анонимный запрос) открыть пост и его файлы.
+// О существовании чужого закрытого поста или черновика не сообщаем
+pub async fn ensure_visible(state: &AppState, post: &Resource, claims: Option<&Claims>) -> Result<(), AppError> {
+    ensure_not_hidden(post)?;
+    // Неопубликованный пост видит только автор, даже администраторы его не видят
+    if post.status != PostStatus::Published {
+        return match claims {
+            Some(claims) if claims.sub == post.author() => Ok(()),
+            _ => Err(AppError::not_found("post", post.id)),
+        };
+    }
+    match post.visibility {
+        Visibility::Public | Visibility::Unlisted => Ok(()),
+        Visibility::MembersOnly if claims.is_some() => Ok(()),
+        Visibility::MembersOnly => Err(AppError::NotAuthorized("sign in to access this post".into())),
+        Visibility::Private => {
+            let Some(claims) = claims else {
+                return Err(AppError::not_found("post", post.id));
+            };
+            if claims.sub == post.author() {
+                return Ok(());
+            }
+            let user: Option<User> = find_record(&claims.sub, &state.db().collection("users")).await?;
+            if user.is_some_and(|v| v.is_admin()) {
+                return Ok(());
+            }
+            Err(AppError::not_found("post", post.id))
+        }
+    }
+}
+
+// Изменять пост и его файлы могут автор и администраторы; action — что именно
+// запрещено, для текста ошибки
+pub async fn ensure_owner(state: &AppState, post: &Resource, claims: &Claims, action: &str) -> Result<(), AppError> {
+    if post.author() == claims.sub {
+        return Ok(());
+    }
+    let user: User = get_record(&claims.sub, &state.db().collection("users")).await?;
+    if !user.is_admin() {
+        return Err(AppError::Forbidden(format!("only the author can {}", action)));
+    }
+    Ok(())
+}
+
+// Условие на посты, которые показываются пользователю в списках: публичные,
+// для вошедших ещё и только для участников, а также собственные посты автора,
+// включая черновики. Полей visibility и status нет у постов, созданных до их
+// появления, такие посты публичные и опубликованные
+pub fn listed_filter(claims: Option<&Claims>) -> Document {
+    let published = vec![Bson::from(PostStatus::Published), Bson::Null];
+    let mut listed = vec![Bson::from(Visibility::Public), Bson::Null];
+    let Some(claims) = claims else {
+        return doc! {"visibility": {"$in": listed}, "status": {"$in": published}};
+    };
+    listed.push(Bson::from(Visibility::MembersOnly));
+    doc! {"$or": [
+        {"visibility": {"$in": listed}, "status": {"$in": published}},
+        {"author": &claims.sub},
+    ]}
+}
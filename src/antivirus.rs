@@ -0,0 +1,223 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::fs;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use crate::config::AntivirusConfig;
+use crate::error::AppError;
+use crate::metrics::METRICS;
+use crate::structures::ScanStatus;
+
+// Ответ clamd длиннее этого считаем ошибкой протокола
+const MAX_REPLY: usize = 4096;
+
+pub struct ScanResult {
+    pub status: ScanStatus,
+    // Название сигнатуры для заражённых файлов или текст ошибки
+    pub details: Option<String>,
+}
+
+// Адрес clamd: tcp://host:port, unix:///path или просто host:port / абсолютный путь
+enum Address {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+impl Address {
+    fn parse(address: &str) -> Address {
+        if let Some(path) = address.strip_prefix("unix://") {
+            Address::Unix(PathBuf::from(path))
+        } else if let Some(host) = address.strip_prefix("tcp://") {
+            Address::Tcp(host.to_string())
+        } else if address.starts_with('/') {
+            Address::Unix(PathBuf::from(address))
+        } else {
+            Address::Tcp(address.to_string())
+        }
+    }
+}
+
+// Протокол INSTREAM: команда, затем блоки "длина (4 байта, big-endian) + данные",
+// нулевой блок завершает поток. Ответ вида "stream: OK" или "stream: <сигнатура> FOUND"
+async fn instream<S, R>(stream: &mut S, source: &mut R, chunk_bytes: usize) -> std::io::Result<String>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    R: AsyncRead + Unpin,
+{
+    stream.write_all(b"zINSTREAM\0").await?;
+    let mut buffer = vec![0; chunk_bytes];
+    loop {
+        let read = source.read(&mut buffer).await?;
+        stream.write_all(&(read as u32).to_be_bytes()).await?;
+        if read == 0 {
+            break;
+        }
+        stream.write_all(&buffer[..read]).await?;
+    }
+    stream.flush().await?;
+
+    let mut reply = Vec::new();
+    let mut byte = [0; 1];
+    while stream.read(&mut byte).await? == 1 && byte[0] != 0 {
+        reply.push(byte[0]);
+        if reply.len() > MAX_REPLY {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "clamd reply too long"));
+        }
+    }
+    Ok(String::from_utf8_lossy(&reply).trim().to_string())
+}
+
+fn parse_reply(reply: &str) -> ScanResult {
+    let body = reply.strip_prefix("stream:").unwrap_or(reply).trim();
+    if body == "OK" {
+        ScanResult { status: ScanStatus::Clean, details: None }
+    } else if let Some(signature) = body.strip_suffix("FOUND") {
+        ScanResult { status: ScanStatus::Infected, details: Some(signature.trim().to_string()) }
+    } else {
+        ScanResult { status: ScanStatus::Failed, details: Some(body.to_string()) }
+    }
+}
+
+async fn send(config: &AntivirusConfig, path: &Path) -> std::io::Result<String> {
+    let mut source = fs::File::open(path).await?;
+    match Address::parse(&config.address) {
+        Address::Tcp(address) => {
+            let mut stream = TcpStream::connect(address).await?;
+            instream(&mut stream, &mut source, config.chunk_bytes).await
+        }
+        #[cfg(unix)]
+        Address::Unix(path) => {
+            let mut stream = tokio::net::UnixStream::connect(path).await?;
+            instream(&mut stream, &mut source, config.chunk_bytes).await
+        }
+        #[cfg(not(unix))]
+        Address::Unix(_) => Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "unix sockets are not supported")),
+    }
+}
+
+// Проверяет файл в clamd. Недоступность демона не ошибка, а статус Failed:
+// решение, отдавать ли такой файл, принимается при скачивании
+#[tracing::instrument(name = "antivirus.scan", skip(config))]
+pub async fn scan_file(config: &AntivirusConfig, path: &Path) -> ScanResult {
+    let timeout = Duration::from_secs(config.timeout_secs);
+    let result = match tokio::time::timeout(timeout, send(config, path)).await {
+        Ok(Ok(reply)) => parse_reply(&reply),
+        Ok(Err(e)) => ScanResult { status: ScanStatus::Failed, details: Some(e.to_string()) },
+        Err(_) => ScanResult { status: ScanStatus::Failed, details: Some("clamd timed out".into()) },
+    };
+
+    METRICS.scans.with_label_values(&[&format!("{:?}", result.status)]).inc();
+    match result.status {
+        ScanStatus::Infected => tracing::warn!(signature = result.details, "infected file"),
+        ScanStatus::Failed => tracing::error!(error = result.details, "antivirus scan failed"),
+        _ => {}
+    }
+    result
+}
+
+// Переносит заражённый файл из uploads в карантин, откуда он не раздаётся
+pub async fn quarantine(config: &AntivirusConfig, path: &Path, post_id: i64, filename: &str) -> Result<PathBuf, AppError> {
+    fs::create_dir_all(&config.quarantine).await?;
+    let target = config.quarantine.join(format!("{}-{}-{}", post_id, uuid::Uuid::new_v4().simple(), filename));
+    if fs::rename(path, &target).await.is_err() {
+        // Карантин может быть на другом разделе
+        fs::copy(path, &target).await?;
+        fs::remove_file(path).await?;
+    }
+    tracing::warn!(post = post_id, file = %filename, quarantine = %target.display(), "file quarantined");
+    Ok(target)
+}
+
+// Проверяет только что сохранённый файл и переносит его в карантин, если он заражён
+pub async fn scan_upload(config: &AntivirusConfig, path: &Path, post_id: i64, filename: &str) -> ScanResult {
+    let mut result = scan_file(config, path).await;
+    if result.status == ScanStatus::Infected {
+        if let Err(e) = quarantine(config, path, post_id, filename).await {
+            // Файл не должен остаться доступным, даже если карантин не удался
+            tracing::error!(post = post_id, file = %filename, error = %e, "failed to quarantine file");
+            if fs::remove_file(path).await.is_err() {
+                result.details = Some(format!("{} (not quarantined)", result.details.unwrap_or_default()));
+            }
+        }
+    }
+    result
+}
+
+pub fn ensure_clean(config: &AntivirusConfig, filename: &str, status: ScanStatus) -> Result<(), AppError> {
+    if config.require_clean && status != ScanStatus::Clean {
+        return Err(AppError::Forbidden(format!(
+            "file {} has not passed the antivirus check ({:?})", filename, status,
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+    use super::*;
+
+    const CONTENT: &[u8] = b"not really a virus";
+
+    // Поддельный clamd: принимает одно соединение, читает поток INSTREAM
+    // и отвечает заданной строкой. Возвращает адрес и полученные данные
+    async fn fake_clamd(reply: &'static str) -> (String, tokio::task::JoinHandle<Vec<u8>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = format!("tcp://{}", listener.local_addr().unwrap());
+        let handle = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut command = [0; 10];
+            stream.read_exact(&mut command).await.unwrap();
+            assert_eq!(&command, b"zINSTREAM\0");
+
+            let mut received = vec![];
+            loop {
+                let length = stream.read_u32().await.unwrap() as usize;
+                if length == 0 {
+                    break;
+                }
+                let mut chunk = vec![0; length];
+                stream.read_exact(&mut chunk).await.unwrap();
+                received.extend(chunk);
+            }
+            stream.write_all(reply.as_bytes()).await.unwrap();
+            stream.write_all(b"\0").await.unwrap();
+            received
+        });
+        (address, handle)
+    }
+
+    async fn scan(reply: &'static str) -> ScanResult {
+        let (address, clamd) = fake_clamd(reply).await;
+        let path = std::env::temp_dir().join(format!("alexandria-scan-{}", uuid::Uuid::new_v4().simple()));
+        fs::write(&path, CONTENT).await.unwrap();
+        // Маленькие блоки, чтобы поток состоял из нескольких частей
+        let config = AntivirusConfig { address, chunk_bytes: 4, ..Default::default() };
+
+        let result = scan_file(&config, &path).await;
+        fs::remove_file(&path).await.unwrap();
+        assert_eq!(clamd.await.unwrap(), CONTENT);
+        result
+    }
+
+    #[tokio::test]
+    async fn clean_reply() {
+        let result = scan("stream: OK").await;
+        assert_eq!(result.status, ScanStatus::Clean);
+        assert_eq!(result.details, None);
+    }
+
+    #[tokio::test]
+    async fn found_reply() {
+        let result = scan("stream: Eicar-Test-Signature FOUND").await;
+        assert_eq!(result.status, ScanStatus::Infected);
+        assert_eq!(result.details.as_deref(), Some("Eicar-Test-Signature"));
+    }
+
+    #[tokio::test]
+    async fn error_reply() {
+        let result = scan("INSTREAM size limit exceeded. ERROR").await;
+        assert_eq!(result.status, ScanStatus::Failed);
+        assert_eq!(result.details.as_deref(), Some("INSTREAM size limit exceeded. ERROR"));
+    }
+}
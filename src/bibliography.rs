@@ -0,0 +1,200 @@
+use bson::{doc, Document};
+use chrono::{Datelike, Utc};
+use serde::Deserialize;
+use utoipa::IntoParams;
+use crate::error::AppError;
+use crate::structures::Bibliography;
+use crate::tags::escape_regex;
+
+const DOI_PREFIXES: &[&str] = &["https://doi.org/", "http://doi.org/", "https://dx.doi.org/", "http://dx.doi.org/", "doi:"];
+
+// Отбор постов по библиографическому описанию; условия объединяются через «и»
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct BibliographyQuery {
+    // Часть имени одного из авторов издания, без учёта регистра
+    authors: Option<String>,
+    // Часть названия издательства, без учёта регистра
+    publisher: Option<String>,
+    year_from: Option<i32>,
+    year_to: Option<i32>,
+    language: Option<String>,
+    isbn: Option<String>,
+    doi: Option<String>,
+}
+
+impl BibliographyQuery {
+    pub fn filter(&self) -> Result<Document, AppError> {
+        let mut filter = doc! {};
+        if let Some(authors) = self.authors.as_deref().map(str::trim).filter(|v| !v.is_empty()) {
+            filter.insert("bibliography.authors", doc! {"$regex": escape_regex(authors), "$options": "i"});
+        }
+        if let Some(publisher) = self.publisher.as_deref().map(str::trim).filter(|v| !v.is_empty()) {
+            filter.insert("bibliography.publisher", doc! {"$regex": escape_regex(publisher), "$options": "i"});
+        }
+        if self.year_from.is_some() || self.year_to.is_some() {
+            let mut year = doc! {};
+            if let Some(from) = self.year_from {
+                year.insert("$gte", from);
+            }
+            if let Some(to) = self.year_to {
+                year.insert("$lte", to);
+            }
+            filter.insert("bibliography.year", year);
+        }
+        if let Some(language) = self.language.as_deref() {
+            filter.insert("bibliography.language", normalize_language(language)?);
+        }
+        if let Some(isbn) = self.isbn.as_deref() {
+            filter.insert("bibliography.isbn", normalize_isbn(isbn)?);
+        }
+        if let Some(doi) = self.doi.as_deref() {
+            filter.insert("bibliography.doi", normalize_doi(doi)?);
+        }
+        Ok(filter)
+    }
+}
+
+// Проверяет описание и приводит его к виду, в котором оно хранится и ищется:
+// ISBN-13 без дефисов, DOI в нижнем регистре без префикса, код языка в нижнем регистре
+pub fn normalize(record: Bibliography) -> Result<Bibliography, AppError> {
+    let year = match record.year {
+        // Допускаем издания, которые выйдут в следующем году
+        Some(year) if year > Utc::now().year() + 1 => return Err(AppError::invalid("year", "is in the future")),
+        year => year,
+    };
+    Ok(Bibliography {
+        authors: record.authors.iter()
+            .map(|v| v.split_whitespace().collect::<Vec<_>>().join(" "))
+            .filter(|v| !v.is_empty())
+            .collect(),
+        publisher: non_empty(record.publisher),
+        year,
+        edition: non_empty(record.edition),
+        language: non_empty(record.language).map(|v| normalize_language(&v)).transpose()?,
+        isbn: non_empty(record.isbn).map(|v| normalize_isbn(&v)).transpose()?,
+        doi: non_empty(record.doi).map(|v| normalize_doi(&v)).transpose()?,
+    })
+}
+
+fn non_empty(value: Option<String>) -> Option<String> {
+    value.map(|v| v.trim().to_string()).filter(|v| !v.is_empty())
+}
+
+// Код языка ISO 639-1 или ISO 639-3
+fn normalize_language(value: &str) -> Result<String, AppError> {
+    let language = value.trim().to_lowercase();
+    if !(2..=3).contains(&language.len()) || !language.chars().all(|c| c.is_ascii_lowercase()) {
+        return Err(AppError::invalid("language", "must be an ISO 639 language code"));
+    }
+    Ok(language)
+}
+
+// ISBN-10 переводится в ISBN-13, чтобы одна книга находилась по любому из номеров
+pub fn normalize_isbn(value: &str) -> Result<String, AppError> {
+    let isbn: String = value.chars()
+        .filter(|c| *c != '-' && !c.is_whitespace())
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    let digits: Vec<u32> = isbn.chars().map(|c| if c == 'X' { 10 } else { c.to_digit(10).unwrap_or(u32::MAX) }).collect();
+    let invalid = || AppError::invalid("isbn", "not a valid ISBN-10 or ISBN-13");
+
+    match digits.len() {
+        10 => {
+            // X допускается только последним символом, как контрольная цифра 10
+            if digits[..9].iter().any(|v| *v > 9) || digits[9] > 10 {
+                return Err(invalid());
+            }
+            let sum: u32 = digits.iter().enumerate().map(|(i, v)| (10 - i as u32) * v).sum();
+            if !sum.is_multiple_of(11) {
+                return Err(invalid());
+            }
+            let body = format!("978{}", &isbn[..9]);
+            Ok(format!("{}{}", body, isbn13_check(&body)))
+        }
+        13 => {
+            if digits.iter().any(|v| *v > 9) || isbn13_check(&isbn[..12]) != digits[12] {
+                return Err(invalid());
+            }
+            Ok(isbn)
+        }
+        _ => Err(invalid()),
+    }
+}
+
+// Контрольная цифра ISBN-13 по первым двенадцати цифрам
+fn isbn13_check(body: &str) -> u32 {
+    let sum: u32 = body.chars()
+        .filter_map(|c| c.to_digit(10))
+        .enumerate()
+        .map(|(i, v)| if i % 2 == 0 { v } else { 3 * v })
+        .sum();
+    (10 - sum % 10) % 10
+}
+
+// DOI вида 10.<регистрант>/<суффикс>; регистр в DOI не различается
+pub fn normalize_doi(value: &str) -> Result<String, AppError> {
+    let mut doi = value.trim().to_lowercase();
+    if let Some(prefix) = DOI_PREFIXES.iter().find(|v| doi.starts_with(*v)) {
+        doi = doi[prefix.len()..].trim_start().to_string();
+    }
+    let invalid = || AppError::invalid("doi", "must look like 10.<registrant>/<suffix>");
+
+    let (prefix, suffix) = doi.split_once('/').ok_or_else(invalid)?;
+    let registrant = prefix.strip_prefix("10.").ok_or_else(invalid)?;
+    let code = registrant.split('.').next().unwrap_or_default();
+    if code.len() < 4
+        || !registrant.split('.').all(|v| !v.is_empty() && v.chars().all(|c| c.is_ascii_digit()))
+        || suffix.is_empty()
+        || suffix.chars().any(char::is_whitespace)
+    {
+        return Err(invalid());
+    }
+    Ok(doi)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn isbn() {
+        // ISBN-10 переводится в ISBN-13, дефисы и пробелы отбрасываются
+        assert_eq!(normalize_isbn("0-306-40615-2").unwrap(), "9780306406157");
+        assert_eq!(normalize_isbn("0 306 40615 2").unwrap(), "9780306406157");
+        assert_eq!(normalize_isbn("978-0-306-40615-7").unwrap(), "9780306406157");
+        // Контрольная цифра X, в том числе строчная
+        assert_eq!(normalize_isbn("080442957X").unwrap(), "9780804429573");
+        assert_eq!(normalize_isbn("0-8044-2957-x").unwrap(), "9780804429573");
+
+        assert!(normalize_isbn("0-306-40615-3").is_err());
+        assert!(normalize_isbn("9780306406158").is_err());
+        assert!(normalize_isbn("X306406152").is_err());
+        assert!(normalize_isbn("978030640615X").is_err());
+        assert!(normalize_isbn("030640615").is_err());
+        assert!(normalize_isbn("").is_err());
+    }
+
+    #[test]
+    fn isbn13_check_digit() {
+        assert_eq!(isbn13_check("978030640615"), 7);
+        assert_eq!(isbn13_check("978080442957"), 3);
+        assert_eq!(isbn13_check("978316148410"), 0);
+    }
+
+    #[test]
+    fn doi() {
+        assert_eq!(normalize_doi("10.1038/nphys1170").unwrap(), "10.1038/nphys1170");
+        assert_eq!(normalize_doi("https://doi.org/10.1038/NPHYS1170").unwrap(), "10.1038/nphys1170");
+        assert_eq!(normalize_doi("https://dx.doi.org/10.1038/nphys1170").unwrap(), "10.1038/nphys1170");
+        assert_eq!(normalize_doi(" DOI:10.1038/nphys1170 ").unwrap(), "10.1038/nphys1170");
+        assert_eq!(normalize_doi("doi: 10.1000.10/abc").unwrap(), "10.1000.10/abc");
+
+        assert!(normalize_doi("11.1000/x").is_err());
+        assert!(normalize_doi("10.12/x").is_err());
+        assert!(normalize_doi("10.1038").is_err());
+        assert!(normalize_doi("10.1038/").is_err());
+        assert!(normalize_doi("10.1038/has space").is_err());
+        assert!(normalize_doi("10.10a8/x").is_err());
+    }
+}
@@ -0,0 +1,578 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use bson::{doc, Document};
+use clap::{Parser, Subcommand};
+use futures_util::TryStreamExt;
+use mongodb::Collection;
+use tokio::fs;
+use uuid::Uuid;
+use crate::AppState;
+use crate::antivirus::scan_upload;
+use crate::db::{create_record, find_record, get_record, update_record};
+use crate::error::AppError;
+use crate::hash::hash_password;
+use crate::indexes::sync_indexes;
+use crate::links::LINKS_COLLECTION;
+use crate::migrations::{applied_migrations, run_migrations, MIGRATIONS};
+use crate::previews::{previews_dir, PREVIEWS_DIR};
+use crate::quota::release;
+use crate::structures::{CreateResource, FileText, Resource, Role, ScanStatus, Stats, User};
+use crate::versions::{versions_dir, PART_PREFIX, VERSIONS_DIR};
+
+#[derive(Parser)]
+#[command(name = "alexandria", version, about = "Alexandria file library server")]
+pub struct Cli {
+    /// Path to the configuration file (default: alexandria.toml)
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run the HTTP server (default)
+    Serve,
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Manage user accounts
+    User {
+        #[command(subcommand)]
+        command: UserCommand,
+    },
+    /// Manage posts
+    Post {
+        #[command(subcommand)]
+        command: PostCommand,
+    },
+    /// Apply pending schema migrations
+    Migrate {
+        /// Only report what would change
+        #[arg(long)]
+        dry_run: bool,
+        /// List applied and pending migrations
+        #[arg(long)]
+        status: bool,
+    },
+    /// Create missing MongoDB indexes and report extra ones
+    Indexes {
+        /// Only report differences
+        #[arg(long)]
+        dry_run: bool,
+        /// Drop indexes that are not declared
+        #[arg(long)]
+        drop_extra: bool,
+    },
+    /// Re-normalize keywords of all posts and subscriptions
+    Reindex,
+    /// Remove uploaded files that no post references and expired download links
+    GcFiles {
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Scan files that have not passed the antivirus check
+    Scan {
+        /// Rescan all files, including clean ones
+        #[arg(long)]
+        all: bool,
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Print instance statistics as JSON
+    Stats,
+    /// Print the OpenAPI specification of the HTTP API
+    Openapi,
+    /// Fill the database with demo users and posts
+    Seed {
+        #[arg(long, default_value_t = 3)]
+        users: u32,
+        #[arg(long, default_value_t = 10)]
+        posts: u32,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// Validate the configuration and exit
+    Check,
+}
+
+#[derive(Subcommand)]
+pub enum UserCommand {
+    /// Create a user, generating a password when none is given
+    Create {
+        email: String,
+        username: String,
+        #[arg(long)]
+        password: Option<String>,
+        #[arg(long)]
+        admin: bool,
+    },
+    /// Disable (or re-enable) a user; existing tokens stop working at once
+    Disable {
+        email: String,
+        #[arg(long)]
+        enable: bool,
+    },
+    /// Set a new password, generating one when none is given
+    ResetPassword {
+        email: String,
+        #[arg(long)]
+        password: Option<String>,
+    },
+    /// Set a storage quota for a user (0 = unlimited), or restore the role quota when none is given
+    Quota {
+        email: String,
+        #[arg(long)]
+        bytes: Option<i64>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum PostCommand {
+    /// Delete a post together with its files
+    Delete {
+        id: i64,
+    },
+    /// Hide (or unhide) a post from listings
+    Hide {
+        id: i64,
+        #[arg(long)]
+        unhide: bool,
+    },
+}
+
+const SEED_TITLES: [&str; 5] = [
+    "Introduction to Algorithms",
+    "The Rust Programming Language",
+    "Structure and Interpretation of Computer Programs",
+    "Designing Data-Intensive Applications",
+    "The Art of Computer Programming",
+];
+
+const SEED_KEYWORDS: [&str; 6] = ["rust", "algorithms", "databases", "lisp", "textbook", "classic"];
+
+fn generate_password() -> String {
+    Uuid::new_v4().simple().to_string()
+}
+
+pub async fn run(command: Command, state: Arc<AppState>) -> Result<(), Box<dyn Error>> {
+    match command {
+        Command::User { command } => run_user(command, &state).await?,
+        Command::Post { command } => run_post(command, &state).await?,
+        Command::Migrate { dry_run, status } => migrate(&state, dry_run, status).await?,
+        Command::Indexes { dry_run, drop_extra } => {
+            for report in sync_indexes(&state.db(), dry_run, drop_extra).await? {
+                println!("{}", report);
+            }
+        }
+        Command::Reindex => reindex(&state).await?,
+        Command::GcFiles { dry_run } => gc_files(&state, dry_run).await?,
+        Command::Scan { all, dry_run } => scan(&state, all, dry_run).await?,
+        Command::Stats => println!("{}", serde_json::to_string_pretty(&stats(&state).await?)?),
+        Command::Seed { users, posts } => seed(&state, users, posts).await?,
+        Command::Serve | Command::Config { .. } | Command::Openapi => unreachable!("handled in main"),
+    }
+    Ok(())
+}
+
+async fn run_user(command: UserCommand, state: &AppState) -> Result<(), AppError> {
+    let users: Collection<User> = state.db().collection("users");
+
+    match command {
+        UserCommand::Create { email, username, password, admin } => {
+            if find_record(&email, &users).await?.is_some() {
+                return Err(AppError::conflict("user", email));
+            }
+            let generated = password.is_none();
+            let password = password.unwrap_or_else(generate_password);
+            let mut user = User::new(email.clone(), username, hash_password(password.clone())?);
+            if admin {
+                user.role = Role::Admin;
+            }
+            create_record(&user, &users).await?;
+            println!("created user {}", email);
+            if generated {
+                println!("password: {}", password);
+            }
+        }
+        UserCommand::Disable { email, enable } => {
+            let mut user = get_record(&email, &users).await?;
+            user.disabled = !enable;
+            update_record(&user.id, &user, &users).await?;
+            println!("user {} {}", email, if enable { "enabled" } else { "disabled" });
+        }
+        UserCommand::ResetPassword { email, password } => {
+            let mut user = get_record(&email, &users).await?;
+            let generated = password.is_none();
+            let password = password.unwrap_or_else(generate_password);
+            user.password_hash = hash_password(password.clone())?;
+            update_record(&user.id, &user, &users).await?;
+            println!("password of {} reset", email);
+            if generated {
+                println!("password: {}", password);
+            }
+        }
+        UserCommand::Quota { email, bytes } => {
+            if bytes.is_some_and(|v| v < 0) {
+                return Err(AppError::invalid("bytes", "must not be negative"));
+            }
+            let mut user = get_record(&email, &users).await?;
+            user.quota_bytes = bytes;
+            update_record(&user.id, &user, &users).await?;
+            match bytes {
+                Some(0) => println!("quota of {} set to unlimited", email),
+                Some(v) => println!("quota of {} set to {} bytes", email, v),
+                None => println!("quota of {} reset to the role default", email),
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn run_post(command: PostCommand, state: &AppState) -> Result<(), AppError> {
+    let db = state.db();
+    let posts: Collection<Resource> = db.collection("posts");
+
+    match command {
+        PostCommand::Delete { id } => {
+            let post = get_record(&id, &posts).await?;
+            posts.delete_one(doc! {"_id": id}).await?;
+            release(&db, &post.files).await?;
+            db.collection::<User>("users")
+                .update_many(doc! {"rated.post": id}, doc! {"$pull": {"rated": {"post": id}}}).await?;
+
+            db.collection::<FileText>("file_texts").delete_many(doc! {"post": id}).await?;
+            db.collection::<Document>(LINKS_COLLECTION).delete_many(doc! {"post": id}).await?;
+
+            let post_dir = state.config.storage.uploads.join(id.to_string());
+            match fs::remove_dir_all(&post_dir).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+            println!("deleted post {}", id);
+        }
+        PostCommand::Hide { id, unhide } => {
+            let mut post = get_record(&id, &posts).await?;
+            post.hidden = !unhide;
+            update_record(&post.id, &post, &posts).await?;
+            println!("post {} {}", id, if unhide { "visible" } else { "hidden" });
+        }
+    }
+    Ok(())
+}
+
+async fn migrate(state: &AppState, dry_run: bool, status: bool) -> Result<(), AppError> {
+    let db = state.db();
+
+    if status {
+        let applied = applied_migrations(&db).await?;
+        for migration in MIGRATIONS {
+            match applied.iter().find(|v| v.version == migration.version) {
+                Some(v) => println!("{:>4} {} applied {}", migration.version, migration.name, v.applied_at.to_rfc3339()),
+                None => println!("{:>4} {} pending", migration.version, migration.name),
+            }
+        }
+        return Ok(());
+    }
+
+    let reports = run_migrations(&db, &state.config.storage.uploads, dry_run).await?;
+    if reports.is_empty() {
+        println!("no pending migrations");
+    }
+    for report in reports {
+        println!(
+            "{:>4} {} {} {} documents",
+            report.version,
+            report.name,
+            if report.applied { "applied to" } else { "would change" },
+            report.documents,
+        );
+    }
+    Ok(())
+}
+
+async fn reindex(state: &AppState) -> Result<(), AppError> {
+    let db = state.db();
+    let posts: Collection<Resource> = db.collection("posts");
+    let users: Collection<User> = db.collection("users");
+
+    let mut updated = 0;
+    let mut cursor = posts.find(doc! {}).await?;
+    while let Some(post) = cursor.try_next().await? {
+        let keywords = state.taxonomy.canonicalize(post.keywords.clone()).await?;
+        if keywords != post.keywords {
+            posts.update_one(doc! {"_id": post.id}, doc! {"$set": {"keywords": keywords}}).await?;
+            updated += 1;
+        }
+    }
+    println!("posts updated: {}", updated);
+
+    let mut updated = 0;
+    let mut cursor = users.find(doc! {}).await?;
+    while let Some(user) = cursor.try_next().await? {
+        let keywords = state.taxonomy.canonicalize(user.followed_keywords.clone()).await?;
+        if keywords != user.followed_keywords {
+            users.update_one(doc! {"_id": &user.id}, doc! {"$set": {"followed_keywords": keywords}}).await?;
+            updated += 1;
+        }
+    }
+    println!("users updated: {}", updated);
+
+    Ok(())
+}
+
+// Временные файлы загрузок моложе этого срока не трогаем: загрузка может ещё идти
+const PART_GRACE: Duration = Duration::from_secs(24 * 60 * 60);
+
+async fn gc_files(state: &AppState, dry_run: bool) -> Result<(), AppError> {
+    let posts: Collection<Resource> = state.db().collection("posts");
+    let uploads = &state.config.storage.uploads;
+    let mut removed = 0;
+
+    let mut entries = fs::read_dir(uploads).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        let post = match entry.file_name().to_str().and_then(|v| v.parse::<i64>().ok()) {
+            Some(id) => find_record(&id, &posts).await?,
+            None => None,
+        };
+
+        let Some(post) = post else {
+            println!("orphaned: {}", path.display());
+            if !dry_run {
+                if entry.file_type().await?.is_dir() {
+                    fs::remove_dir_all(&path).await?;
+                } else {
+                    fs::remove_file(&path).await?;
+                }
+            }
+            removed += 1;
+            continue;
+        };
+
+        let known: HashSet<&str> = post.files.iter().map(|v| v.filename.as_str()).collect();
+        let previews: HashSet<&str> = post.files.iter()
+            .filter_map(|v| v.preview_url.as_deref())
+            .filter_map(|v| v.rsplit('/').next())
+            .collect();
+        removed += remove_unknown(&previews_dir(&path), &previews, dry_run).await?;
+        removed += remove_unknown_versions(&versions_dir(&path), &post, dry_run).await?;
+
+        let mut files = fs::read_dir(&path).await?;
+        while let Some(file) = files.next_entry().await? {
+            let name = file.file_name();
+            if name == PREVIEWS_DIR || name == VERSIONS_DIR || known.contains(name.to_string_lossy().as_ref()) {
+                continue;
+            }
+            // Свежий временный файл может принадлежать загрузке, которая ещё идёт
+            if name.to_string_lossy().starts_with(PART_PREFIX) && is_recent(&file.metadata().await?) {
+                continue;
+            }
+            println!("orphaned: {}", file.path().display());
+            if !dry_run {
+                fs::remove_file(file.path()).await?;
+            }
+            removed += 1;
+        }
+    }
+
+    // Извлечённый текст файлов, которых больше нет в постах
+    let texts: Collection<Document> = state.db().collection("file_texts");
+    let mut cursor = texts.find(doc! {}).projection(doc! {"post": 1, "filename": 1}).await?;
+    while let Some(text) = cursor.try_next().await? {
+        let (Ok(id), Ok(post), Ok(filename)) = (text.get_str("_id"), text.get_i64("post"), text.get_str("filename")) else {
+            continue;
+        };
+        let known = find_record(&post, &posts).await?
+            .is_some_and(|v| v.files.iter().any(|f| f.filename == filename));
+        if known {
+            continue;
+        }
+        println!("orphaned text: {}", id);
+        if !dry_run {
+            texts.delete_one(doc! {"_id": id}).await?;
+        }
+        removed += 1;
+    }
+
+    // Подписанные ссылки с истёкшим сроком
+    let links: Collection<Document> = state.db().collection(LINKS_COLLECTION);
+    let filter = doc! {"expires_at": {"$lt": bson::DateTime::now()}};
+    let expired = if dry_run {
+        links.count_documents(filter).await?
+    } else {
+        links.delete_many(filter).await?.deleted_count
+    };
+    if expired > 0 {
+        println!("expired links: {}", expired);
+    }
+    removed += expired;
+
+    println!("{} {} entries", if dry_run { "would remove" } else { "removed" }, removed);
+    Ok(())
+}
+
+fn is_recent(metadata: &std::fs::Metadata) -> bool {
+    let age = metadata.modified().ok().and_then(|v| SystemTime::now().duration_since(v).ok());
+    age.is_none_or(|v| v < PART_GRACE)
+}
+
+// Удаляет из каталога файлы, которых нет в списке; отсутствующий каталог не ошибка
+async fn remove_unknown(dir: &Path, known: &HashSet<&str>, dry_run: bool) -> Result<u64, AppError> {
+    let mut entries = match fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e.into()),
+    };
+    let mut removed = 0;
+    while let Some(entry) = entries.next_entry().await? {
+        if known.contains(entry.file_name().to_string_lossy().as_ref()) {
+            continue;
+        }
+        println!("orphaned: {}", entry.path().display());
+        if !dry_run {
+            fs::remove_file(entry.path()).await?;
+        }
+        removed += 1;
+    }
+    Ok(removed)
+}
+
+async fn scan(state: &AppState, all: bool, dry_run: bool) -> Result<(), AppError> {
+    let config = &state.config.antivirus;
+    if !config.enabled && !dry_run {
+        return Err(AppError::BadRequest("antivirus scanning is disabled in the configuration".into()));
+    }
+    let posts: Collection<Resource> = state.db().collection("posts");
+    let mut scanned = 0;
+
+    let mut cursor = posts.find(doc! {}).await?;
+    while let Some(post) = cursor.try_next().await? {
+        let post_dir = state.config.storage.uploads.join(post.id.to_string());
+        for file in post.files.iter() {
+            // Заражённые файлы уже в карантине, на диске их нет
+            let pending = matches!(file.scan_status, ScanStatus::Unscanned | ScanStatus::Failed);
+            if file.scan_status == ScanStatus::Infected || !(all || pending) {
+                continue;
+            }
+            scanned += 1;
+            if dry_run {
+                println!("would scan: {}/{}", post.id, file.filename);
+                continue;
+            }
+
+            let result = scan_upload(config, &post_dir.join(&file.filename), post.id, &file.filename).await;
+            println!("{}/{}: {:?}{}", post.id, file.filename, result.status,
+                result.details.as_deref().map(|v| format!(" ({})", v)).unwrap_or_default());
+            posts.update_one(
+                doc! {"_id": post.id, "files.filename": &file.filename},
+                doc! {"$set": {"files.$.scan_status": result.status, "files.$.scan_details": result.details}},
+            ).await?;
+        }
+    }
+
+    println!("{} {} files", if dry_run { "would scan" } else { "scanned" }, scanned);
+    Ok(())
+}
+
+// Версии файлов, которых нет в истории: .versions/<имя файла>/<номер версии>
+async fn remove_unknown_versions(dir: &Path, post: &Resource, dry_run: bool) -> Result<u64, AppError> {
+    let mut entries = match fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e.into()),
+    };
+    let mut removed = 0;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name();
+        match post.files.iter().find(|v| v.filename == name.to_string_lossy()) {
+            Some(file) => {
+                let versions: Vec<String> = file.versions.iter().map(|v| v.version.to_string()).collect();
+                let known: HashSet<&str> = versions.iter().map(String::as_str).collect();
+                removed += remove_unknown(&entry.path(), &known, dry_run).await?;
+            }
+            None => {
+                println!("orphaned: {}", entry.path().display());
+                if !dry_run {
+                    fs::remove_dir_all(entry.path()).await?;
+                }
+                removed += 1;
+            }
+        }
+    }
+    Ok(removed)
+}
+
+async fn stats(state: &AppState) -> Result<Stats, AppError> {
+    let db = state.db();
+    let users: Collection<User> = db.collection("users");
+    let posts: Collection<Resource> = db.collection("posts");
+
+    let totals: Option<Document> = posts.aggregate(vec![
+        doc! {"$group": {
+            "_id": null,
+            "files": {"$sum": {"$size": "$files"}},
+            "bytes": {"$sum": {"$sum": "$files.size"}},
+        }},
+    ]).await?.try_next().await?;
+    let total = |key: &str| totals.as_ref()
+        .and_then(|v| v.get(key))
+        .and_then(|v| v.as_i64().or_else(|| v.as_i32().map(i64::from)))
+        .unwrap_or(0) as u64;
+
+    Ok(Stats {
+        users: users.count_documents(doc! {}).await?,
+        admins: users.count_documents(doc! {"role": "Admin"}).await?,
+        disabled_users: users.count_documents(doc! {"disabled": true}).await?,
+        posts: posts.count_documents(doc! {}).await?,
+        hidden_posts: posts.count_documents(doc! {"hidden": true}).await?,
+        files: total("files"),
+        stored_bytes: total("bytes"),
+    })
+}
+
+async fn seed(state: &AppState, users_count: u32, posts_count: u32) -> Result<(), AppError> {
+    let db = state.db();
+    let users: Collection<User> = db.collection("users");
+    let posts: Collection<Resource> = db.collection("posts");
+
+    let mut authors = vec![];
+    for i in 1..=users_count.max(1) {
+        let email = format!("demo{}@example.com", i);
+        let user = match find_record(&email, &users).await? {
+            Some(user) => user,
+            None => {
+                let user = User::new(email.clone(), format!("demo{}", i), hash_password("demo".into())?);
+                create_record(&user, &users).await?;
+                println!("created user {} (password: demo)", email);
+                user
+            }
+        };
+        authors.push(user);
+    }
+
+    for i in 0..posts_count as usize {
+        let author = &authors[i % authors.len()];
+        let keywords = vec![
+            SEED_KEYWORDS[i % SEED_KEYWORDS.len()].to_string(),
+            SEED_KEYWORDS[(i + 2) % SEED_KEYWORDS.len()].to_string(),
+        ];
+        let mut post = CreateResource::new(
+            SEED_TITLES[i % SEED_TITLES.len()].to_string(),
+            "Demo post created by `alexandria seed`".to_string(),
+            keywords,
+        ).into_resource(author.id.clone(), author.username.clone(), &state.id_gen, &state.taxonomy).await?;
+        post.publish();
+        create_record(&post, &posts).await?;
+    }
+    println!("created {} posts", posts_count);
+
+    Ok(())
+}
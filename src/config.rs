@@ -0,0 +1,567 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use serde::Deserialize;
+use crate::db::RetryPolicy;
+
+pub const DEFAULT_CONFIG_PATH: &str = "alexandria.toml";
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    pub storage: StorageConfig,
+    pub jwt: JwtConfig,
+    pub limits: LimitsConfig,
+    pub logging: LoggingConfig,
+    pub tls: TlsConfig,
+    pub http: HttpConfig,
+    pub previews: PreviewConfig,
+    pub text: TextConfig,
+    pub antivirus: AntivirusConfig,
+    pub quotas: QuotaConfig,
+    pub links: LinkConfig,
+    pub publishing: PublishingConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub listen: String,
+    // Сколько ждать завершения текущих запросов после SIGTERM/SIGINT
+    pub shutdown_timeout_secs: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub uri: String,
+    pub username: String,
+    pub password: String,
+    pub name: String,
+    pub retries: u32,
+    pub retry_backoff_ms: u64,
+    pub retry_max_backoff_ms: u64,
+    pub migrate_on_startup: bool,
+    pub sync_indexes: bool,
+    pub drop_extra_indexes: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub uploads: PathBuf,
+    // Ниже этого порога свободного места /readyz отвечает 503
+    pub min_free_bytes: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    pub enabled: bool,
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    // Как часто проверять, не сменились ли файлы сертификата и ключа
+    pub reload_interval_secs: u64,
+    // Адрес дополнительного HTTP-слушателя, перенаправляющего на HTTPS
+    pub redirect_listen: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    // Источники, которым разрешены запросы из браузера; "*" — любой
+    pub cors_origins: Vec<String>,
+    // 0 отключает заголовок Strict-Transport-Security
+    pub hsts_max_age_secs: u64,
+    pub content_security_policy: String,
+    pub compression: bool,
+    // Не применяется к загрузке и скачиванию файлов
+    pub request_timeout_secs: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PreviewConfig {
+    pub enabled: bool,
+    // Наибольшая сторона превью в пикселях
+    pub size: u32,
+    // Файлы крупнее этого размера пропускаются
+    pub max_source_bytes: u64,
+    // Утилита poppler для рендеринга первой страницы PDF
+    pub pdftoppm: PathBuf,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TextConfig {
+    pub enabled: bool,
+    // Утилита poppler для извлечения текста PDF
+    pub pdftotext: PathBuf,
+    // Сколько байт текста хранить на файл (документ MongoDB ограничен 16 МиБ)
+    pub max_bytes: usize,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AntivirusConfig {
+    pub enabled: bool,
+    // tcp://host:port, unix:///path/to/clamd.ctl, host:port или абсолютный путь к сокету
+    pub address: String,
+    pub timeout_secs: u64,
+    // Размер блока INSTREAM; не больше StreamMaxLength в clamd.conf
+    pub chunk_bytes: usize,
+    // Каталог для заражённых файлов, не должен раздаваться
+    pub quarantine: PathBuf,
+    // Отдавать только файлы со статусом Clean
+    pub require_clean: bool,
+}
+
+// Квоты ролей в байтах; 0 — без ограничения
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuotaConfig {
+    pub enabled: bool,
+    pub user_bytes: u64,
+    pub admin_bytes: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LinkConfig {
+    // Ключ подписи ссылок; пустой — используется jwt.secret
+    pub secret: String,
+    pub default_ttl_secs: u64,
+    pub max_ttl_secs: u64,
+    // Адрес клиента для привязки берётся из X-Forwarded-For; только за доверенным прокси
+    pub trust_forwarded_for: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PublishingConfig {
+    // Как часто фоновая задача публикует посты, время публикации которых наступило
+    pub interval_secs: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JwtConfig {
+    pub secret: String,
+    pub token_lifetime_secs: i64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    pub max_upload_bytes: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Pretty,
+    Json,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    pub format: LogFormat,
+    // Фильтр в формате RUST_LOG, например "info,alexandria=debug"
+    pub filter: String,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig { listen: "0.0.0.0:3000".into(), shutdown_timeout_secs: 30 }
+    }
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        let retry = RetryPolicy::default();
+        DatabaseConfig {
+            uri: String::new(),
+            username: String::new(),
+            password: String::new(),
+            name: "alexandria".into(),
+            retries: retry.retries,
+            retry_backoff_ms: retry.backoff.as_millis() as u64,
+            retry_max_backoff_ms: retry.max_backoff.as_millis() as u64,
+            migrate_on_startup: true,
+            sync_indexes: true,
+            drop_extra_indexes: false,
+        }
+    }
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig { uploads: PathBuf::from("uploads"), min_free_bytes: 1024 * 1024 * 1024 }
+    }
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        TlsConfig {
+            enabled: false,
+            cert: PathBuf::new(),
+            key: PathBuf::new(),
+            reload_interval_secs: 30,
+            redirect_listen: None,
+        }
+    }
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            cors_origins: vec![],
+            hsts_max_age_secs: 60 * 60 * 24 * 180,
+            content_security_policy: "default-src 'none'; frame-ancestors 'none'".into(),
+            compression: true,
+            request_timeout_secs: 30,
+        }
+    }
+}
+
+impl Default for PreviewConfig {
+    fn default() -> Self {
+        PreviewConfig {
+            enabled: true,
+            size: 320,
+            max_source_bytes: 100 * 1024 * 1024,
+            pdftoppm: PathBuf::from("pdftoppm"),
+        }
+    }
+}
+
+impl Default for TextConfig {
+    fn default() -> Self {
+        TextConfig {
+            enabled: true,
+            pdftotext: PathBuf::from("pdftotext"),
+            max_bytes: 4 * 1024 * 1024,
+        }
+    }
+}
+
+impl Default for AntivirusConfig {
+    fn default() -> Self {
+        AntivirusConfig {
+            enabled: false,
+            address: "tcp://127.0.0.1:3310".into(),
+            timeout_secs: 60,
+            chunk_bytes: 64 * 1024,
+            quarantine: PathBuf::from("quarantine"),
+            require_clean: false,
+        }
+    }
+}
+
+impl Default for QuotaConfig {
+    fn default() -> Self {
+        QuotaConfig { enabled: false, user_bytes: 10 * 1024 * 1024 * 1024, admin_bytes: 0 }
+    }
+}
+
+impl Default for LinkConfig {
+    fn default() -> Self {
+        LinkConfig {
+            secret: String::new(),
+            default_ttl_secs: 24 * 60 * 60,
+            max_ttl_secs: 30 * 24 * 60 * 60,
+            trust_forwarded_for: false,
+        }
+    }
+}
+
+impl Default for PublishingConfig {
+    fn default() -> Self {
+        PublishingConfig { interval_secs: 60 }
+    }
+}
+
+impl Default for JwtConfig {
+    fn default() -> Self {
+        JwtConfig { secret: String::new(), token_lifetime_secs: 60 * 60 * 24 * 7 }
+    }
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        LimitsConfig { max_upload_bytes: 1024 * 1024 * 1024 }
+    }
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig { format: LogFormat::Pretty, filter: "info".into() }
+    }
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pretty" => Ok(LogFormat::Pretty),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format `{}`", s)),
+        }
+    }
+}
+
+impl From<&DatabaseConfig> for RetryPolicy {
+    fn from(value: &DatabaseConfig) -> Self {
+        RetryPolicy {
+            retries: value.retries,
+            backoff: Duration::from_millis(value.retry_backoff_ms),
+            max_backoff: Duration::from_millis(value.retry_max_backoff_ms),
+        }
+    }
+}
+
+fn parse_env<T: std::str::FromStr>(name: &str, errors: &mut Vec<String>) -> Option<T> {
+    let value = std::env::var(name).ok()?;
+    match value.parse() {
+        Ok(v) => Some(v),
+        Err(_) => {
+            errors.push(format!("{}: cannot parse `{}`", name, value));
+            None
+        }
+    }
+}
+
+impl Config {
+    // Читаем TOML-файл (если он есть) и поверх него переменные окружения
+    pub fn load(path: Option<&Path>) -> Result<Config, Vec<String>> {
+        let explicit = path.map(Path::to_path_buf)
+            .or_else(|| std::env::var("ALEXANDRIA_CONFIG").ok().map(PathBuf::from));
+        let path = explicit.clone().unwrap_or_else(|| PathBuf::from(DEFAULT_CONFIG_PATH));
+
+        let mut config = match std::fs::read_to_string(&path) {
+            Ok(text) => toml::from_str(&text)
+                .map_err(|e| vec![format!("{}: {}", path.display(), e)])?,
+            Err(e) if explicit.is_none() && e.kind() == std::io::ErrorKind::NotFound => Config::default(),
+            Err(e) => return Err(vec![format!("{}: {}", path.display(), e)]),
+        };
+
+        let mut errors = config.apply_env();
+        errors.extend(config.validate());
+        if errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(errors)
+        }
+    }
+
+    fn apply_env(&mut self) -> Vec<String> {
+        let mut errors = vec![];
+        let string = |name: &str| std::env::var(name).ok();
+
+        if let Some(v) = string("SERVER_URL") { self.server.listen = v; }
+        if let Some(v) = parse_env("SHUTDOWN_TIMEOUT_SECS", &mut errors) { self.server.shutdown_timeout_secs = v; }
+        if let Some(v) = string("MONGODB_URI") { self.database.uri = v; }
+        if let Some(v) = string("MONGO_USERNAME") { self.database.username = v; }
+        if let Some(v) = string("PASSWORD") { self.database.password = v; }
+        if let Some(v) = string("DB_NAME") { self.database.name = v; }
+        if let Some(v) = parse_env("DB_RETRIES", &mut errors) { self.database.retries = v; }
+        if let Some(v) = parse_env("DB_RETRY_BACKOFF_MS", &mut errors) { self.database.retry_backoff_ms = v; }
+        if let Some(v) = parse_env("DB_RETRY_MAX_BACKOFF_MS", &mut errors) { self.database.retry_max_backoff_ms = v; }
+        if let Some(v) = parse_env("DB_MIGRATE_ON_STARTUP", &mut errors) { self.database.migrate_on_startup = v; }
+        if let Some(v) = parse_env("DB_SYNC_INDEXES", &mut errors) { self.database.sync_indexes = v; }
+        if let Some(v) = parse_env("DB_DROP_EXTRA_INDEXES", &mut errors) { self.database.drop_extra_indexes = v; }
+        if let Some(v) = string("UPLOADS_DIR") { self.storage.uploads = PathBuf::from(v); }
+        if let Some(v) = parse_env("MIN_FREE_BYTES", &mut errors) { self.storage.min_free_bytes = v; }
+        if let Some(v) = string("SECRET") { self.jwt.secret = v; }
+        if let Some(v) = parse_env("TOKEN_LIFETIME_SECS", &mut errors) { self.jwt.token_lifetime_secs = v; }
+        if let Some(v) = parse_env("MAX_UPLOAD_BYTES", &mut errors) { self.limits.max_upload_bytes = v; }
+        if let Some(v) = parse_env("LOG_FORMAT", &mut errors) { self.logging.format = v; }
+        if let Some(v) = string("RUST_LOG") { self.logging.filter = v; }
+        if let Some(v) = parse_env("TLS_ENABLED", &mut errors) { self.tls.enabled = v; }
+        if let Some(v) = string("TLS_CERT") { self.tls.cert = PathBuf::from(v); }
+        if let Some(v) = string("TLS_KEY") { self.tls.key = PathBuf::from(v); }
+        if let Some(v) = parse_env("TLS_RELOAD_INTERVAL_SECS", &mut errors) { self.tls.reload_interval_secs = v; }
+        if let Some(v) = string("TLS_REDIRECT_LISTEN") { self.tls.redirect_listen = Some(v); }
+        if let Some(v) = string("CORS_ORIGINS") {
+            self.http.cors_origins = v.split(',').map(|v| v.trim().to_string()).filter(|v| !v.is_empty()).collect();
+        }
+        if let Some(v) = parse_env("HSTS_MAX_AGE_SECS", &mut errors) { self.http.hsts_max_age_secs = v; }
+        if let Some(v) = string("CONTENT_SECURITY_POLICY") { self.http.content_security_policy = v; }
+        if let Some(v) = parse_env("COMPRESSION", &mut errors) { self.http.compression = v; }
+        if let Some(v) = parse_env("REQUEST_TIMEOUT_SECS", &mut errors) { self.http.request_timeout_secs = v; }
+        if let Some(v) = parse_env("PREVIEWS_ENABLED", &mut errors) { self.previews.enabled = v; }
+        if let Some(v) = parse_env("PREVIEW_SIZE", &mut errors) { self.previews.size = v; }
+        if let Some(v) = parse_env("PREVIEW_MAX_SOURCE_BYTES", &mut errors) { self.previews.max_source_bytes = v; }
+        if let Some(v) = string("PDFTOPPM") { self.previews.pdftoppm = PathBuf::from(v); }
+        if let Some(v) = parse_env("TEXT_EXTRACTION_ENABLED", &mut errors) { self.text.enabled = v; }
+        if let Some(v) = string("PDFTOTEXT") { self.text.pdftotext = PathBuf::from(v); }
+        if let Some(v) = parse_env("TEXT_MAX_BYTES", &mut errors) { self.text.max_bytes = v; }
+        if let Some(v) = parse_env("ANTIVIRUS_ENABLED", &mut errors) { self.antivirus.enabled = v; }
+        if let Some(v) = string("CLAMD_ADDRESS") { self.antivirus.address = v; }
+        if let Some(v) = parse_env("CLAMD_TIMEOUT_SECS", &mut errors) { self.antivirus.timeout_secs = v; }
+        if let Some(v) = parse_env("CLAMD_CHUNK_BYTES", &mut errors) { self.antivirus.chunk_bytes = v; }
+        if let Some(v) = string("QUARANTINE_DIR") { self.antivirus.quarantine = PathBuf::from(v); }
+        if let Some(v) = parse_env("ANTIVIRUS_REQUIRE_CLEAN", &mut errors) { self.antivirus.require_clean = v; }
+        if let Some(v) = parse_env("QUOTAS_ENABLED", &mut errors) { self.quotas.enabled = v; }
+        if let Some(v) = parse_env("QUOTA_USER_BYTES", &mut errors) { self.quotas.user_bytes = v; }
+        if let Some(v) = parse_env("QUOTA_ADMIN_BYTES", &mut errors) { self.quotas.admin_bytes = v; }
+        if let Some(v) = string("LINK_SECRET") { self.links.secret = v; }
+        if let Some(v) = parse_env("LINK_DEFAULT_TTL_SECS", &mut errors) { self.links.default_ttl_secs = v; }
+        if let Some(v) = parse_env("LINK_MAX_TTL_SECS", &mut errors) { self.links.max_ttl_secs = v; }
+        if let Some(v) = parse_env("TRUST_FORWARDED_FOR", &mut errors) { self.links.trust_forwarded_for = v; }
+        if let Some(v) = parse_env("PUBLISH_INTERVAL_SECS", &mut errors) { self.publishing.interval_secs = v; }
+
+        errors
+    }
+
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = vec![];
+
+        if !self.server.listen.contains(':') {
+            errors.push(format!("server.listen: `{}` is not a host:port address", self.server.listen));
+        }
+        if !self.database.uri.starts_with("mongodb://") && !self.database.uri.starts_with("mongodb+srv://") {
+            errors.push("database.uri: must start with mongodb:// or mongodb+srv://".into());
+        }
+        if self.database.username.is_empty() {
+            errors.push("database.username: must not be empty".into());
+        }
+        if self.database.name.is_empty() {
+            errors.push("database.name: must not be empty".into());
+        }
+        if self.storage.uploads.as_os_str().is_empty() {
+            errors.push("storage.uploads: must not be empty".into());
+        }
+        if self.jwt.secret.is_empty() {
+            errors.push("jwt.secret: must not be empty".into());
+        }
+        if self.links.default_ttl_secs == 0 || self.links.default_ttl_secs > self.links.max_ttl_secs {
+            errors.push("links.default_ttl_secs: must be positive and not exceed links.max_ttl_secs".into());
+        }
+        if self.publishing.interval_secs == 0 {
+            errors.push("publishing.interval_secs: must be positive".into());
+        }
+        if self.jwt.token_lifetime_secs <= 0 {
+            errors.push("jwt.token_lifetime_secs: must be positive".into());
+        }
+        if self.limits.max_upload_bytes == 0 {
+            errors.push("limits.max_upload_bytes: must be positive".into());
+        }
+        if let Err(e) = tracing_subscriber::EnvFilter::try_new(&self.logging.filter) {
+            errors.push(format!("logging.filter: {}", e));
+        }
+        if self.tls.enabled {
+            if self.tls.cert.as_os_str().is_empty() || self.tls.key.as_os_str().is_empty() {
+                errors.push("tls: cert and key are required when TLS is enabled".into());
+            }
+            if self.tls.reload_interval_secs == 0 {
+                errors.push("tls.reload_interval_secs: must be positive".into());
+            }
+        }
+        if let Some(listen) = self.tls.redirect_listen.as_ref() {
+            if !self.tls.enabled {
+                errors.push("tls.redirect_listen: requires TLS to be enabled".into());
+            }
+            if !listen.contains(':') {
+                errors.push(format!("tls.redirect_listen: `{}` is not a host:port address", listen));
+            }
+        }
+        for origin in self.http.cors_origins.iter().filter(|v| *v != "*") {
+            if origin.ends_with('/') || axum::http::HeaderValue::from_str(origin).is_err() {
+                errors.push(format!("http.cors_origins: `{}` is not an origin like https://example.com", origin));
+            }
+        }
+        if axum::http::HeaderValue::from_str(&self.http.content_security_policy).is_err() {
+            errors.push("http.content_security_policy: not a valid header value".into());
+        }
+        if self.http.request_timeout_secs == 0 {
+            errors.push("http.request_timeout_secs: must be positive".into());
+        }
+        if self.previews.size == 0 {
+            errors.push("previews.size: must be positive".into());
+        }
+        if self.antivirus.enabled {
+            if self.antivirus.address.is_empty() {
+                errors.push("antivirus.address: must not be empty".into());
+            }
+            if self.antivirus.chunk_bytes == 0 || self.antivirus.timeout_secs == 0 {
+                errors.push("antivirus: chunk_bytes and timeout_secs must be positive".into());
+            }
+        }
+        if self.antivirus.quarantine.starts_with(&self.storage.uploads) {
+            errors.push("antivirus.quarantine: must not be inside storage.uploads, which is served publicly".into());
+        }
+        if self.text.max_bytes > 15 * 1024 * 1024 {
+            errors.push("text.max_bytes: must fit in a MongoDB document (at most 15 MiB)".into());
+        }
+
+        errors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VALID: &str = r#"
+        [database]
+        uri = "mongodb://localhost:27017"
+        username = "alexandria"
+
+        [jwt]
+        secret = "secret"
+    "#;
+
+    #[test]
+    fn valid_config() {
+        let config: Config = toml::from_str(VALID).unwrap();
+        assert_eq!(config.validate(), Vec::<String>::new());
+    }
+
+    // Проверка не останавливается на первой ошибке: сообщается обо всех сразу
+    #[test]
+    fn reports_every_error() {
+        let config: Config = toml::from_str(r#"
+            [server]
+            listen = "8080"
+
+            [database]
+            uri = "localhost:27017"
+            username = "alexandria"
+
+            [jwt]
+            secret = ""
+            token_lifetime_secs = 0
+
+            [publishing]
+            interval_secs = 0
+
+            [http]
+            cors_origins = ["https://example.com/"]
+
+            [storage]
+            uploads = "data"
+
+            [antivirus]
+            quarantine = "data/quarantine"
+        "#).unwrap();
+
+        let errors = config.validate();
+        let fields = [
+            "server.listen",
+            "database.uri",
+            "jwt.secret",
+            "jwt.token_lifetime_secs",
+            "publishing.interval_secs",
+            "http.cors_origins",
+            "antivirus.quarantine",
+        ];
+        for field in fields {
+            assert!(errors.iter().any(|v| v.starts_with(field)), "{} is not reported in {:?}", field, errors);
+        }
+        assert_eq!(errors.len(), fields.len(), "{:?}", errors);
+    }
+}
@@ -1,45 +1,141 @@
-use bson::{doc, Bson};
+use std::future::IntoFuture;
+use std::sync::OnceLock;
+use std::time::Duration;
+use bson::{doc, Bson, Document};
 use mongodb::Collection;
+use mongodb::error::{ErrorKind, RETRYABLE_WRITE_ERROR, TRANSIENT_TRANSACTION_ERROR};
 use mongodb::results::{InsertOneResult, UpdateResult};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use crate::error::AppError;
 
-pub async fn get_record<T, S>(id: &S, coll: &Collection<T>) -> Result<T, AppError>
+// Коды ошибок сервера, после которых запрос можно повторить
+const RETRYABLE_CODES: [i32; 12] = [6, 7, 89, 91, 189, 262, 9001, 10107, 11600, 11602, 13435, 13436];
+
+static RETRY_POLICY: OnceLock<RetryPolicy> = OnceLock::new();
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub retries: u32,
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            retries: 3,
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+        }
+    }
+}
+
+impl RetryPolicy {
+    fn delay(&self, attempt: u32) -> Duration {
+        self.backoff.saturating_mul(2u32.saturating_pow(attempt)).min(self.max_backoff)
+    }
+}
+
+pub fn set_retry_policy(policy: RetryPolicy) {
+    let _ = RETRY_POLICY.set(policy);
+}
+
+fn retry_policy() -> &'static RetryPolicy {
+    RETRY_POLICY.get_or_init(RetryPolicy::default)
+}
+
+// Сетевые сбои, таймауты и перевыборы primary считаем временными
+pub fn is_transient(error: &mongodb::error::Error) -> bool {
+    if error.contains_label(RETRYABLE_WRITE_ERROR) || error.contains_label(TRANSIENT_TRANSACTION_ERROR) {
+        return true;
+    }
+    match error.kind.as_ref() {
+        ErrorKind::Io(_)
+        | ErrorKind::ServerSelection { .. }
+        | ErrorKind::ConnectionPoolCleared { .. }
+        | ErrorKind::DnsResolve { .. } => true,
+        ErrorKind::Command(e) => RETRYABLE_CODES.contains(&e.code),
+        _ => false,
+    }
+}
+
+// Повторяем операцию с экспоненциальной задержкой, пока ошибка временная
+pub async fn with_retry<T, F, Fut>(mut operation: F) -> Result<T, mongodb::error::Error>
+where
+    F: FnMut() -> Fut,
+    Fut: IntoFuture<Output = Result<T, mongodb::error::Error>>,
+{
+    let policy = retry_policy();
+    let mut attempt = 0;
+    loop {
+        match operation().await {
+            Err(e) if attempt < policy.retries && is_transient(&e) => {
+                tracing::warn!(attempt = attempt + 1, error = %e, "transient database error, retrying");
+                tokio::time::sleep(policy.delay(attempt)).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+// Название сущности для ошибок по имени коллекции
+pub fn entity_name<T: Send + Sync>(coll: &Collection<T>) -> String {
+    match coll.name() {
+        "posts" => "post".into(),
+        "users" => "user".into(),
+        "counters" => "counter".into(),
+        "tag_aliases" => "tag_alias".into(),
+        "file_texts" => "file_text".into(),
+        "download_links" => "download_link".into(),
+        other => other.to_string(),
+    }
+}
+
+#[tracing::instrument(name = "db.find_one", skip_all, fields(collection = coll.name()))]
+pub async fn find_record<T, S>(id: &S, coll: &Collection<T>) -> Result<Option<T>, AppError>
 where
     T: Send + Sync + DeserializeOwned,
     S: Clone, Bson: From<S>
 {
-    if let Ok(Some(rec)) = coll.find_one(doc! {"_id": id}).await {
-        Ok(rec)
-    } else {
-        Err(AppError::NotFound)
-    }
+    Ok(with_retry(|| coll.find_one(doc! {"_id": id})).await?)
 }
 
+pub async fn get_record<T, S>(id: &S, coll: &Collection<T>) -> Result<T, AppError>
+where
+    T: Send + Sync + DeserializeOwned,
+    S: Clone + ToString, Bson: From<S>
+{
+    find_record(id, coll).await?
+        .ok_or_else(|| AppError::not_found(&entity_name(coll), id.to_string()))
+}
+
+#[tracing::instrument(name = "db.update_one", skip_all, fields(collection = coll.name()))]
 pub async fn update_record<T, S>(id: &S, rec: &T, coll: &Collection<T>)
     -> Result<UpdateResult, AppError>
 where
     T: Send + Sync + DeserializeOwned + Into<Bson> + Clone,
     S: Clone, Bson: From<S>
 {
-    if let Ok(result) = coll.update_one(
+    Ok(with_retry(|| coll.update_one(
         doc! {"_id": id},
         doc! {"$set": rec}
-    ).await {
-        Ok(result)
-    } else {
-        Err(AppError::InternalServerError)
-    }
+    )).await?)
 }
 
+// Записываем через From<T> for Bson, чтобы даты сохранялись как BSON datetime.
+// Вставка не повторяется: если пропал лишь ответ сервера, повтор вернул бы
+// ложный конфликт по ключу для уже записанного документа
+#[tracing::instrument(name = "db.insert_one", skip_all, fields(collection = coll.name()))]
 pub async fn create_record<T>(rec: &T, coll: &Collection<T>) -> Result<InsertOneResult, AppError>
 where
-    T: Send + Sync + DeserializeOwned + Serialize + Into<Bson>
+    T: Send + Sync + DeserializeOwned + Serialize + Into<Bson> + Clone
 {
-    if let Ok(result) = coll.insert_one(rec).await {
-        Ok(result)
-    } else {
-        Err(AppError::InternalServerError)
-    }
+    let document = match rec.clone().into() {
+        Bson::Document(document) => document,
+        other => return Err(AppError::InternalServerError(format!("cannot insert {:?}", other.element_type()))),
+    };
+    let coll = coll.clone_with_type::<Document>();
+    Ok(coll.insert_one(&document).await?)
 }
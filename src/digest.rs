@@ -0,0 +1,136 @@
+use std::path::Path;
+use sha2::{Digest as _, Sha256};
+use tokio::fs;
+use tokio::io::AsyncReadExt;
+
+// Сколько первых байт файла нужно для определения типа по сигнатуре
+const HEAD_BYTES: usize = 8192;
+
+// Считает SHA-256 и размер по мере поступления данных и запоминает начало файла,
+// чтобы определить MIME-тип по содержимому, а не по расширению
+#[derive(Default)]
+pub struct FileDigest {
+    hasher: Sha256,
+    head: Vec<u8>,
+    size: u64,
+}
+
+pub struct FileMetadata {
+    pub sha256: String,
+    pub mime_type: String,
+    pub size: u64,
+}
+
+impl FileDigest {
+    pub fn update(&mut self, chunk: &[u8]) {
+        self.hasher.update(chunk);
+        if self.head.len() < HEAD_BYTES {
+            let take = (HEAD_BYTES - self.head.len()).min(chunk.len());
+            self.head.extend_from_slice(&chunk[..take]);
+        }
+        self.size += chunk.len() as u64;
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn finish(self) -> FileMetadata {
+        FileMetadata {
+            sha256: hex::encode(self.hasher.finalize()),
+            mime_type: sniff_mime(&self.head),
+            size: self.size,
+        }
+    }
+}
+
+pub fn sniff_mime(head: &[u8]) -> String {
+    if let Some(kind) = infer::get(head) {
+        return kind.mime_type().to_string();
+    }
+    // Сигнатуры нет: текст, если начало файла — корректный UTF-8 (с учётом обрезанного символа)
+    let text = match std::str::from_utf8(head) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none() && head.len() == HEAD_BYTES,
+    };
+    if text && !head.contains(&0) { "text/plain" } else { "application/octet-stream" }.to_string()
+}
+
+pub async fn digest_file(path: &Path) -> std::io::Result<FileMetadata> {
+    let mut file = fs::File::open(path).await?;
+    let mut digest = FileDigest::default();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        digest.update(&buffer[..read]);
+    }
+    Ok(digest.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn epub() -> Vec<u8> {
+        let mut head = b"PK\x03\x04".to_vec();
+        head.resize(30, 0);
+        head.extend_from_slice(b"mimetypeapplication/epub+zip");
+        head
+    }
+
+    #[test]
+    fn signatures() {
+        let cases: &[(&[u8], &str)] = &[
+            (b"%PDF-1.7\n%\xe2\xe3\xcf\xd3\n", "application/pdf"),
+            (b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR", "image/png"),
+            (b"\xff\xd8\xff\xe0\0\x10JFIF\0", "image/jpeg"),
+            (b"GIF89a\x01\0\x01\0", "image/gif"),
+            (b"RIFF\x24\0\0\0WEBPVP8 ", "image/webp"),
+            (b"PK\x03\x04\x14\0\0\0\x08\0", "application/zip"),
+            (&epub(), "application/epub+zip"),
+            (b"\x1f\x8b\x08\0\0\0\0\0", "application/gzip"),
+        ];
+        for (head, mime) in cases {
+            assert_eq!(sniff_mime(head), *mime, "{:?}", String::from_utf8_lossy(head));
+        }
+    }
+
+    #[test]
+    fn fallback() {
+        // Начало файла обрывается на первом байте двухбайтового символа
+        let mut cut = format!("{}a", "ж".repeat(HEAD_BYTES / 2 - 1)).into_bytes();
+        cut.push("ж".as_bytes()[0]);
+        assert_eq!(cut.len(), HEAD_BYTES);
+        let cases: &[(&[u8], &str)] = &[
+            (b"", "text/plain"),
+            (b"plain text\nwith lines", "text/plain"),
+            ("текст в UTF-8".as_bytes(), "text/plain"),
+            // Символ обрезан границей HEAD_BYTES, а не испорчен; в коротком файле это ошибка
+            (&cut, "text/plain"),
+            ("обрезан".as_bytes().split_last().unwrap().1, "application/octet-stream"),
+            (b"text\0with nul", "application/octet-stream"),
+            (b"\xff\xfe\xfd binary", "application/octet-stream"),
+        ];
+        for (head, mime) in cases {
+            assert_eq!(sniff_mime(head), *mime, "{:?}", String::from_utf8_lossy(head));
+        }
+    }
+
+    #[test]
+    fn digest_keeps_head_across_chunks() {
+        let mut digest = FileDigest::default();
+        digest.update(b"%PD");
+        digest.update(b"F-1.4\n");
+        digest.update(&vec![b'x'; HEAD_BYTES * 2]);
+        let metadata = digest.finish();
+        assert_eq!(metadata.mime_type, "application/pdf");
+        assert_eq!(metadata.size, 9 + HEAD_BYTES as u64 * 2);
+
+        let mut digest = FileDigest::default();
+        digest.update(b"abc");
+        assert_eq!(digest.finish().sha256, "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    }
+}
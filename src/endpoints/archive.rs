@@ -0,0 +1,258 @@
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use async_compression::tokio::write::GzipEncoder;
+use async_zip::base::write::ZipFileWriter;
+use async_zip::{Compression, ZipEntryBuilder};
+use axum::body::Body;
+use axum::extract::{Path, State};
+use axum::http::header::{ACCEPT, CONTENT_DISPOSITION, CONTENT_TYPE};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Extension;
+use axum_extra::extract::Query;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use tokio::io::{AsyncRead, AsyncWriteExt, DuplexStream, ReadBuf};
+use tokio_util::compat::FuturesAsyncWriteCompatExt;
+use tokio_util::io::ReaderStream;
+use utoipa::{IntoParams, ToSchema};
+use crate::AppState;
+use crate::access::ensure_visible;
+use crate::antivirus::ensure_clean;
+use crate::db::get_record;
+use crate::digest::FileDigest;
+use crate::error::{AppError, ErrorBody};
+use crate::metrics::METRICS;
+use crate::structures::{Claims, File, Resource, ScanStatus};
+
+const MANIFEST: &str = "manifest.json";
+const CHECKSUMS: &str = "SHA256SUMS";
+// Буфер между задачей, пишущей архив, и телом ответа
+const PIPE_BYTES: usize = 256 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
+pub enum ArchiveFormat {
+    #[serde(rename = "zip")]
+    Zip,
+    #[serde(rename = "tar.gz")]
+    TarGz,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ArchiveParams {
+    // Формат архива; без параметра выбирается по Accept, по умолчанию zip
+    format: Option<ArchiveFormat>,
+    // Имена файлов для архива; без параметра — все файлы поста
+    #[serde(default)]
+    files: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct ManifestFile {
+    filename: String,
+    size: u64,
+    sha256: String,
+    mime_type: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Manifest {
+    post: i64,
+    title: String,
+    files: Vec<ManifestFile>,
+}
+
+impl ArchiveFormat {
+    fn from_accept(headers: &HeaderMap) -> Option<ArchiveFormat> {
+        let accept = headers.get(ACCEPT)?.to_str().ok()?;
+        if accept.contains("application/zip") {
+            Some(ArchiveFormat::Zip)
+        } else if accept.contains("application/gzip") || accept.contains("application/x-gtar") {
+            Some(ArchiveFormat::TarGz)
+        } else {
+            None
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "application/zip",
+            ArchiveFormat::TarGz => "application/gzip",
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::TarGz => "tar.gz",
+        }
+    }
+}
+
+// Читатель, попутно считающий контрольную сумму и размер того, что через него прошло
+struct HashingReader<R> {
+    inner: R,
+    digest: FileDigest,
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for HashingReader<R> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        let result = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = result {
+            this.digest.update(&buf.filled()[before..]);
+        }
+        result
+    }
+}
+
+fn archive_error(e: impl std::fmt::Display) -> AppError {
+    AppError::Io(e.to_string())
+}
+
+fn manifest_entries(manifest: &Manifest) -> Result<Vec<(&'static str, Vec<u8>)>, AppError> {
+    let checksums: String = manifest.files.iter().map(|v| format!("{}  {}\n", v.sha256, v.filename)).collect();
+    Ok(vec![
+        (MANIFEST, serde_json::to_vec_pretty(manifest).map_err(archive_error)?),
+        (CHECKSUMS, checksums.into_bytes()),
+    ])
+}
+
+fn manifest_file(file: &File, digest: FileDigest) -> ManifestFile {
+    let metadata = digest.finish();
+    ManifestFile {
+        filename: file.filename.clone(),
+        size: metadata.size,
+        sha256: metadata.sha256,
+        mime_type: file.mime_type.clone(),
+    }
+}
+
+// Файлы пишутся без сжатия: книги и изображения обычно уже сжаты.
+// Контрольные суммы считаются по фактически отправленным данным и попадают в манифест в конце архива
+async fn write_zip(writer: DuplexStream, post_dir: PathBuf, post: Resource, files: Vec<File>) -> Result<(), AppError> {
+    let mut zip = ZipFileWriter::with_tokio(writer);
+    let mut manifest = Manifest { post: post.id, title: post.title, files: vec![] };
+
+    for file in files.iter() {
+        let mut reader = HashingReader { inner: fs::File::open(post_dir.join(&file.filename)).await?, digest: FileDigest::default() };
+        let entry = ZipEntryBuilder::new(file.filename.clone().into(), Compression::Stored);
+        let mut entry = zip.write_entry_stream(entry).await.map_err(archive_error)?.compat_write();
+        tokio::io::copy(&mut reader, &mut entry).await?;
+        entry.into_inner().close().await.map_err(archive_error)?;
+        manifest.files.push(manifest_file(file, reader.digest));
+    }
+
+    for (name, data) in manifest_entries(&manifest)? {
+        let entry = ZipEntryBuilder::new(name.to_string().into(), Compression::Stored);
+        zip.write_entry_whole(entry, &data).await.map_err(archive_error)?;
+    }
+    zip.close().await.map_err(archive_error)?.into_inner().shutdown().await?;
+    Ok(())
+}
+
+async fn write_tar_gz(writer: DuplexStream, post_dir: PathBuf, post: Resource, files: Vec<File>) -> Result<(), AppError> {
+    let mut tar = tokio_tar::Builder::new(GzipEncoder::new(writer));
+    let mut manifest = Manifest { post: post.id, title: post.title, files: vec![] };
+
+    let header = |size: u64| {
+        let mut header = tokio_tar::Header::new_gnu();
+        header.set_size(size);
+        header.set_mode(0o644);
+        header.set_mtime(chrono::Utc::now().timestamp() as u64);
+        header
+    };
+
+    for file in files.iter() {
+        let source = fs::File::open(post_dir.join(&file.filename)).await?;
+        let size = source.metadata().await?.len();
+        let mut reader = HashingReader { inner: source, digest: FileDigest::default() };
+        tar.append_data(&mut header(size), &file.filename, &mut reader).await?;
+        manifest.files.push(manifest_file(file, reader.digest));
+    }
+
+    for (name, data) in manifest_entries(&manifest)? {
+        tar.append_data(&mut header(data.len() as u64), name, data.as_slice()).await?;
+    }
+    let mut gzip = tar.into_inner().await?;
+    gzip.shutdown().await?;
+    Ok(())
+}
+
+// Архив всех (или выбранных) файлов поста, формируемый на лету без временных файлов
+#[utoipa::path(
+    get,
+    path = "/posts/{post_id}/archive",
+    tag = "files",
+    params(("post_id" = i64, Path), ArchiveParams),
+    responses(
+        (status = 200, content_type = "application/zip", body = Vec<u8>),
+        (status = 403, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    ),
+)]
+pub async fn download_post_archive(
+    Path(post_id): Path<i64>,
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<ArchiveParams>,
+    headers: HeaderMap,
+    claims: Option<Extension<Claims>>,
+) -> Result<Response, AppError> {
+    let post: Resource = get_record(&post_id, &state.db().collection("posts")).await?;
+    ensure_visible(&state, &post, claims.as_deref()).await?;
+
+    // Заражённые файлы лежат в карантине: без списка их пропускаем, а явно названный — ошибка
+    let files: Vec<File> = if params.files.is_empty() {
+        post.files.iter().filter(|v| v.scan_status != ScanStatus::Infected).cloned().collect()
+    } else {
+        let mut selected: Vec<File> = vec![];
+        for name in params.files.iter() {
+            if selected.iter().any(|v| &v.filename == name) {
+                continue;
+            }
+            let file = post.files.iter().find(|v| &v.filename == name)
+                .ok_or_else(|| AppError::not_found("file", name))?;
+            if file.scan_status == ScanStatus::Infected {
+                return Err(AppError::Forbidden(format!("file {} is infected and was quarantined", name)));
+            }
+            selected.push(file.clone());
+        }
+        selected
+    };
+
+    // Проверяем файлы до начала ответа: потом ошибку уже не вернуть статусом
+    let post_dir = state.config.storage.uploads.join(post_id.to_string());
+    for file in files.iter() {
+        ensure_clean(&state.config.antivirus, &file.filename, file.scan_status)?;
+        if let Err(e) = fs::metadata(post_dir.join(&file.filename)).await {
+            return Err(match e.kind() {
+                std::io::ErrorKind::NotFound => AppError::not_found("file", &file.filename),
+                _ => e.into(),
+            });
+        }
+    }
+    METRICS.downloaded_bytes.inc_by(files.iter().map(|v| v.size as u64).sum());
+
+    let format = params.format.or_else(|| ArchiveFormat::from_accept(&headers)).unwrap_or(ArchiveFormat::Zip);
+    let (writer, reader) = tokio::io::duplex(PIPE_BYTES);
+    tokio::spawn(async move {
+        let result = match format {
+            ArchiveFormat::Zip => write_zip(writer, post_dir, post, files).await,
+            ArchiveFormat::TarGz => write_tar_gz(writer, post_dir, post, files).await,
+        };
+        // Клиент получит оборванный архив; статус к этому моменту уже отправлен
+        if let Err(e) = result {
+            tracing::warn!(post = post_id, error = %e, "failed to stream archive");
+        }
+    });
+
+    let headers = [
+        (CONTENT_TYPE, format.content_type().to_string()),
+        (CONTENT_DISPOSITION, format!("attachment; filename=\"post-{}.{}\"", post_id, format.extension())),
+    ];
+    Ok((StatusCode::OK, headers, Body::from_stream(ReaderStream::new(reader))).into_response())
+}
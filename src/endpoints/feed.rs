@@ -0,0 +1,155 @@
+use std::sync::Arc;
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::{Extension, Json};
+use bson::doc;
+use chrono::{DateTime, Utc};
+use futures_util::TryStreamExt;
+use mongodb::Collection;
+use serde::Deserialize;
+use utoipa::IntoParams;
+use crate::AppState;
+use crate::access::listed_filter;
+use crate::bibliography::BibliographyQuery;
+use crate::db::{get_record, update_record};
+use crate::error::{AppError, ErrorBody};
+use crate::structures::{Claims, Feed, FeedCursor, Follow, Resource, User};
+
+const FEED_PAGE: i64 = 10;
+const FEED_MAX_PAGE: i64 = 50;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct FeedParams {
+    // Курсор из next_cursor предыдущей страницы: время публикации и id последнего поста
+    before_time: Option<DateTime<Utc>>,
+    before: Option<i64>,
+    limit: Option<i64>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/follow",
+    tag = "feed",
+    request_body = Follow,
+    responses(
+        (status = 200),
+        (status = 404, body = ErrorBody),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn follow(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Json(mut payload): Json<Follow>,
+) -> Result<StatusCode, AppError> {
+    let users: Collection<User> = state.db().collection("users");
+    let mut user = get_record(&claims.sub, &users).await?;
+    payload.keywords = state.taxonomy.canonicalize(payload.keywords).await?;
+
+    // Подписаться можно только на существующих пользователей
+    for id in payload.users.iter() {
+        get_record(id, &users).await?;
+    }
+
+    user.follow(payload);
+    update_record(&user.id, &user, &users).await?;
+
+    Ok(StatusCode::OK)
+}
+
+#[utoipa::path(
+    post,
+    path = "/unfollow",
+    tag = "feed",
+    request_body = Follow,
+    responses((status = 200)),
+    security(("bearer" = [])),
+)]
+pub async fn unfollow(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Json(mut payload): Json<Follow>,
+) -> Result<StatusCode, AppError> {
+    let users: Collection<User> = state.db().collection("users");
+    let mut user = get_record(&claims.sub, &users).await?;
+    payload.keywords = state.taxonomy.canonicalize(payload.keywords).await?;
+
+    user.unfollow(payload);
+    update_record(&user.id, &user, &users).await?;
+
+    Ok(StatusCode::OK)
+}
+
+// Лента из новых постов отслеживаемых авторов и ключевых слов
+#[utoipa::path(
+    get,
+    path = "/feed",
+    tag = "feed",
+    params(FeedParams, BibliographyQuery),
+    responses(
+        (status = 200, body = Feed),
+        (status = 400, body = ErrorBody),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn get_feed(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Query(params): Query<FeedParams>,
+    Query(bibliography): Query<BibliographyQuery>,
+) -> Result<Json<Feed>, AppError> {
+    let db = state.db();
+    let posts: Collection<Resource> = db.collection("posts");
+    let user: User = get_record(&claims.sub, &db.collection("users")).await?;
+
+    if user.following.is_empty() && user.followed_keywords.is_empty() {
+        return Ok(Json(Feed { posts: vec![], next_cursor: None }));
+    }
+
+    let mut conditions = vec![
+        doc! {"$or": [
+            {"author": {"$in": &user.following}},
+            {"keywords": {"$in": &user.followed_keywords}},
+        ]},
+        listed_filter(Some(&claims)),
+    ];
+    // Лента упорядочена по времени публикации, а не по id: черновик, опубликованный
+    // позже, получает новое время при старом id. Поэтому курсор — пара (время, id)
+    match (params.before_time, params.before) {
+        (Some(time), Some(id)) => {
+            let time = bson::DateTime::from_chrono(time);
+            conditions.push(doc! {"$or": [
+                {"upload_time": {"$lt": time}},
+                {"upload_time": time, "_id": {"$lt": id}},
+            ]});
+        }
+        (None, None) => {}
+        _ => return Err(AppError::invalid("before", "before and before_time must be given together")),
+    }
+    let mut filter = doc! {"hidden": {"$ne": true}, "$and": conditions};
+    filter.extend(bibliography.filter()?);
+    let limit = params.limit.unwrap_or(FEED_PAGE).clamp(1, FEED_MAX_PAGE);
+
+    let result: Vec<Resource> = posts.find(filter)
+        .sort(doc! {"upload_time": -1, "_id": -1})
+        .limit(limit)
+        .await?
+        .try_collect()
+        .await?;
+
+    let next_cursor = if result.len() as i64 == limit {
+        result.last().map(|v| FeedCursor { before_time: v.upload_time(), before: v.id })
+    } else {
+        None
+    };
+
+    let posts = result.into_iter()
+        .map(|post| {
+            let rating = user.get_rating(post.id);
+            post.into_send_resource(rating)
+        })
+        .collect();
+
+    Ok(Json(Feed { posts, next_cursor }))
+}
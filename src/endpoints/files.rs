@@ -1,73 +1,169 @@
-use axum::{extract::{Multipart, Path}, response::{IntoResponse, Response}, http::StatusCode, debug_handler};
-use serde::{Serialize};
+use axum::{extract::{ConnectInfo, Multipart, Path, Query}, response::{IntoResponse, Response}, http::StatusCode, debug_handler, Json};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use std::io::ErrorKind;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::sync::Arc;
 use axum::extract::State;
+use axum::extract::multipart::Field;
+use axum::Extension;
+use chrono::Utc;
+use axum::http::header::{CACHE_CONTROL, CONTENT_LENGTH, CONTENT_TYPE};
+use axum::http::HeaderMap;
 use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tracing::{info_span, Instrument};
 use crate::AppState;
+use crate::access::{ensure_not_hidden, ensure_owner, ensure_visible};
+use crate::antivirus::{ensure_clean, scan_upload, ScanResult};
 use crate::db::{get_record, update_record};
-use crate::structures::{File, Resource};
+use crate::digest::{FileDigest, FileMetadata};
+use crate::error::{AppError, ErrorBody};
+use crate::extract::extract_texts;
+use crate::links::{client_ip, redeem, verify};
+use crate::metrics::METRICS;
+use crate::previews::{generate_previews, previews_dir};
+use crate::quota::{allowance, charge, Allowance};
+use crate::structures::{Claims, File, FileVersion, PostStatus, Resource, ScanStatus, User, Visibility};
+use crate::versions::{content_path, keep_version, part_path, replace_current, version_path};
 
 // Структура для ответа
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct UploadResponse {
     success: bool,
     message: String,
     file_paths: Vec<String>,
 }
 
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DownloadParams {
+    // Номер версии; без параметра — текущая
+    version: Option<i64>,
+    // Подписанная ссылка: id, срок действия (unix time) и подпись
+    link: Option<String>,
+    expires: Option<i64>,
+    signature: Option<String>,
+}
+
 // Создаем путь к директории для конкретного поста
-pub async fn ensure_post_directory(post_id: i64) -> std::io::Result<PathBuf> {
-    let post_dir = PathBuf::from("uploads").join(post_id.to_string());
+#[tracing::instrument(name = "fs.create_dir", skip(uploads))]
+pub async fn ensure_post_directory(uploads: &std::path::Path, post_id: i64) -> std::io::Result<PathBuf> {
+    let post_dir = uploads.join(post_id.to_string());
     fs::create_dir_all(&post_dir).await?;
     Ok(post_dir)
 }
 
+// Превью и извлечение текста для новых или изменившихся файлов поста
+fn spawn_processing(state: &AppState, post_id: i64, post_dir: PathBuf, filenames: Vec<String>) {
+    if filenames.is_empty() {
+        return;
+    }
+    if state.config.previews.enabled {
+        tokio::spawn(generate_previews(
+            state.config.previews.clone(),
+            state.db(),
+            post_id,
+            post_dir.clone(),
+            filenames.clone(),
+        ));
+    }
+    if state.config.text.enabled {
+        tokio::spawn(extract_texts(
+            state.config.text.clone(),
+            state.db(),
+            post_id,
+            post_dir,
+            filenames,
+        ));
+    }
+}
+
+// Запись прерывается, как только файл перестаёт помещаться в квоту
+async fn save_field(mut field: Field<'_>, path: &std::path::Path, allowance: Option<Allowance>) -> Result<FileMetadata, AppError> {
+    let mut file = fs::File::create(path).await?;
+    let mut digest = FileDigest::default();
+    while let Some(chunk) = field.chunk().await? {
+        digest.update(&chunk);
+        if let Some(allowance) = allowance {
+            allowance.check(digest.size())?;
+        }
+        file.write_all(&chunk).await?;
+    }
+    file.flush().await?;
+    Ok(digest.finish())
+}
+
+#[utoipa::path(
+    post,
+    path = "/posts/{post_id}/upload",
+    tag = "files",
+    params(("post_id" = i64, Path)),
+    request_body(
+        content_type = "multipart/form-data",
+        description = "Files to attach to the post; a `description` text part applies to the file part after it",
+    ),
+    responses(
+        (status = 200, body = UploadResponse),
+        (status = 403, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+        (status = 413, body = ErrorBody),
+    ),
+    security(("bearer" = [])),
+)]
 #[debug_handler]
 // Обработчик загрузки файлов для конкретного поста
 pub async fn upload_files_to_post(
     Path(post_id): Path<i64>,
     State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    headers: HeaderMap,
     mut multipart: Multipart,
-) -> Response {
-    let mut post = match get_record(&post_id, &state.client.database("alexandria").collection::<Resource>("posts")).await {
-        Err(_) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "post doesn't exist".to_string(),
-            )
-                .into_response();
-        }
-        Ok(record) => record
-    };
+) -> Result<Json<UploadResponse>, AppError> {
+    let mut post = get_record(&post_id, &state.db().collection::<Resource>("posts")).await?;
+    ensure_visible(&state, &post, Some(&claims)).await?;
+    ensure_owner(&state, &post, &claims, "upload files to this post").await?;
+    let user: User = get_record(&claims.sub, &state.db().collection("users")).await?;
+    let mut allowance = allowance(&state.config.quotas, &user);
+
+    // Размер тела известен заранее: отказываем, не читая его
+    let content_length = headers.get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    if let (Some(allowance), Some(length)) = (allowance, content_length) {
+        allowance.check(length)?;
+    }
 
     // Проверяем и создаем директорию для поста
-    let post_dir = match ensure_post_directory(post_id).await {
-        Ok(dir) => dir,
-        Err(e) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Failed to create directory: {}", e),
-            )
-                .into_response();
-        }
-    };
+    let post_dir = ensure_post_directory(&state.config.storage.uploads, post_id).await?;
 
     let mut uploaded_files = Vec::new();
+    let mut infected_files = Vec::new();
     let mut had_errors = false;
+    let mut quota_error = None;
+    // Обрыв тела запроса: уже перенесённые файлы и списанная квота должны попасть в пост
+    let mut body_error = None;
+
+    // Описание из текстового поля "description" относится к следующему за ним файлу
+    let mut description: Option<String> = None;
 
     // Обрабатываем каждый файл в multipart-запросе
-    while let Ok(Some(field)) = multipart.next_field().await {
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => {
+                body_error = Some(AppError::from(e));
+                break;
+            }
+        };
         let file_name = match field.file_name() {
             Some(name) => sanitize_filename::sanitize(name),
-            None => continue,
-        };
-
-        let data = match field.bytes().await {
-            Ok(data) => data,
-            Err(e) => {
-                had_errors = true;
-                eprintln!("Error reading file data: {}", e);
+            None => {
+                if field.name() == Some("description") {
+                    description = Some(field.text().await?).filter(|v| !v.trim().is_empty());
+                }
                 continue;
             }
         };
@@ -75,30 +171,118 @@ pub async fn upload_files_to_post(
         // Создаем полный путь к файлу
         let file_path = post_dir.join(&file_name);
 
-        // Сохраняем файл
-        if let Err(e) = fs::write(&file_path, &data).await {
+        // Файл с тем же именем становится прежней версией и продолжает занимать место
+        let previous = post.files.iter().find(|v| v.filename == file_name).cloned();
+
+        // Пишем во временный файл, чтобы текущая версия осталась, если загрузка не удастся
+        let part_path = part_path(&post_dir);
+        let save = save_field(field, &part_path, allowance)
+            .instrument(info_span!("fs.write", path = %file_path.display()));
+        let saved = match save.await {
+            Ok(metadata) => {
+                let size = metadata.size as i64;
+                charge(&state.db(), &claims.sub, size, allowance.map(|v| v.quota)).await.map(|_| (metadata, size))
+            }
+            Err(e) => Err(e),
+        };
+        let (metadata, size) = match saved {
+            Ok(saved) => saved,
+            Err(e) => {
+                let _ = fs::remove_file(&part_path).await;
+                if let AppError::QuotaExceeded { .. } = e {
+                    // Остаток тела не читаем
+                    quota_error = Some(e);
+                    break;
+                }
+                had_errors = true;
+                tracing::warn!(file = %file_name, error = %e, "failed to save uploaded file");
+                continue;
+            }
+        };
+        // Проверяем временный файл, пока он не доступен по публичному пути. Заражённый
+        // уходит в карантин, но запись о нём с причиной остаётся в посте
+        let scan = if state.config.antivirus.enabled {
+            scan_upload(&state.config.antivirus, &part_path, post_id, &file_name).await
+        } else {
+            ScanResult { status: ScanStatus::Unscanned, details: None }
+        };
+        let stored = match (scan.status, previous.as_ref()) {
+            // Прежняя версия всё равно уходит в историю: текущей становится заражённая
+            (ScanStatus::Infected, Some(previous)) => keep_version(&post_dir, &file_name, previous.version).await,
+            (ScanStatus::Infected, None) => Ok(()),
+            _ => replace_current(&post_dir, &part_path, &file_name, previous.as_ref().map(|v| v.version)).await,
+        };
+        if let Err(e) = stored {
             had_errors = true;
-            eprintln!("Error saving file: {}", e);
+            tracing::warn!(file = %file_name, error = %e, "failed to save uploaded file");
+            let _ = fs::remove_file(&part_path).await;
+            let _ = charge(&state.db(), &claims.sub, -size, None).await;
             continue;
         }
-        
+        allowance = allowance.map(|v| Allowance { used: v.used + size as u64, ..v });
+
+        METRICS.uploaded_files.inc();
+        METRICS.uploaded_bytes.inc_by(metadata.size);
+
+        // Запись о прежней версии переходит в историю нового файла
+        let (version, versions) = match previous {
+            Some(previous) => {
+                let mut versions = previous.versions.clone();
+                versions.push((&previous).into());
+                (previous.version + 1, versions)
+            }
+            None => (1, vec![]),
+        };
+        post.files.retain(|v| v.filename != file_name);
         post.files.push(File {
             filename: file_name.clone(),
-            size: data.len().try_into().unwrap(),
+            size: metadata.size as i64,
+            preview_url: None,
+            text_extracted: false,
+            mime_type: Some(metadata.mime_type),
+            sha256: Some(metadata.sha256),
+            uploader: Some(claims.sub.clone()),
+            uploaded_at: Some(Utc::now()),
+            description: description.take(),
+            scan_status: scan.status,
+            scan_details: scan.details,
+            version,
+            versions,
         });
-        
+
+        if scan.status == ScanStatus::Infected {
+            infected_files.push(file_name);
+            continue;
+        }
+
         // Добавляем путь к файлу в список успешно загруженных
-        uploaded_files.push(format!("{}", file_name));
+        uploaded_files.push(file_name);
     }
     
-    if let Err(e) = update_record(&post_id, &post, &state.client.database("alexandria").collection::<Resource>("posts")).await {
-        return e.into_response();
+    update_record(&post_id, &post, &state.db().collection::<Resource>("posts")).await?;
+
+    // Превью и текст для поиска готовятся в фоне, ответ на загрузку их не ждёт
+    spawn_processing(&state, post_id, post_dir, uploaded_files.clone());
+
+    if let Some(e) = body_error {
+        tracing::warn!(post_id, saved = uploaded_files.len(), error = %e, "upload body ended early");
+        return Err(e);
+    }
+
+    // Если ничего не сохранено, отвечаем ошибкой квоты; иначе сообщаем о частичной загрузке
+    let quota_exceeded = quota_error.is_some();
+    if let Some(e) = quota_error.filter(|_| uploaded_files.is_empty() && infected_files.is_empty()) {
+        return Err(e);
     }
 
     // Формируем ответ
     let response = UploadResponse {
-        success: !had_errors && !uploaded_files.is_empty(),
-        message: if had_errors {
+        success: !had_errors && !quota_exceeded && infected_files.is_empty() && !uploaded_files.is_empty(),
+        message: if !infected_files.is_empty() {
+            format!("Infected files were quarantined: {}", infected_files.join(", "))
+        } else if quota_exceeded {
+            "Storage quota exceeded, some files were not uploaded".to_string()
+        } else if had_errors {
             "Some files failed to upload".to_string()
         } else if uploaded_files.is_empty() {
             "No files were uploaded".to_string()
@@ -108,17 +292,63 @@ pub async fn upload_files_to_post(
         file_paths: uploaded_files,
     };
 
-    (StatusCode::OK, axum::Json(response)).into_response()
+    Ok(Json(response))
 }
 
 // Обработчик скачивания файла для конкретного поста
+#[utoipa::path(
+    get,
+    path = "/posts/{post_id}/files/{filename}",
+    tag = "files",
+    params(("post_id" = i64, Path), ("filename" = String, Path), DownloadParams),
+    responses(
+        (status = 200, content_type = "application/octet-stream", body = Vec<u8>),
+        (status = 403, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    ),
+)]
 pub async fn download_post_file(
     Path((post_id, filename)): Path<(i64, String)>,
-) -> Response {
-    let file_path = PathBuf::from("uploads").join(&post_id.to_string()).join(&filename);
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<DownloadParams>,
+    claims: Option<Extension<Claims>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let post = get_record(&post_id, &state.db().collection::<Resource>("posts")).await?;
+    // Подписанная ссылка заменяет проверку видимости: её выдал автор поста
+    let link = match params.link.as_deref() {
+        Some(link) => {
+            ensure_not_hidden(&post)?;
+            let ip = client_ip(&state.config, &headers, peer);
+            Some(verify(&state, post_id, &filename, link, params.expires, params.signature.as_deref(), ip).await?)
+        }
+        None => {
+            ensure_visible(&state, &post, claims.as_deref()).await?;
+            None
+        }
+    };
+    let version = match link.as_ref() {
+        Some(link) => link.version,
+        None => params.version,
+    };
+    let file = post.files.iter().find(|v| v.filename == filename)
+        .ok_or_else(|| AppError::not_found("file", &filename))?;
+    let version = version.unwrap_or(file.version);
+    let entry = file.find_version(version)
+        .ok_or_else(|| AppError::not_found("version", version))?;
+    ensure_clean(&state.config.antivirus, &filename, entry.scan_status)?;
+
+    let post_dir = state.config.storage.uploads.join(post_id.to_string());
+    let file_path = content_path(&post_dir, &filename, file.version, version);
 
-    match fs::read(&file_path).await {
+    match fs::read(&file_path).instrument(info_span!("fs.read", path = %file_path.display())).await {
         Ok(data) => {
+            // Скачивание по ссылке засчитывается, только когда файл действительно отдаётся
+            if let Some(link) = link.as_ref() {
+                redeem(&state, link).await?;
+            }
+            METRICS.downloaded_bytes.inc_by(data.len() as u64);
             let headers = [
                 ("Content-Type", "application/octet-stream"),
                 (
@@ -126,31 +356,221 @@ pub async fn download_post_file(
                     &format!("attachment; filename=\"{}\"", filename),
                 ),
             ];
-            (StatusCode::OK, headers, data).into_response()
+            Ok((StatusCode::OK, headers, data).into_response())
         }
-        Err(_) => (StatusCode::NOT_FOUND, "File not found").into_response(),
+        Err(e) if e.kind() == ErrorKind::NotFound => Err(AppError::not_found("file", filename)),
+        Err(e) => Err(e.into()),
     }
 }
 
 // Получение списка файлов поста
-pub async fn list_post_files(Path(post_id): Path<String>) -> Response {
-    let post_dir = PathBuf::from("uploads").join(&post_id);
+#[utoipa::path(
+    get,
+    path = "/posts/{post_id}/files",
+    tag = "files",
+    params(("post_id" = i64, Path)),
+    responses(
+        (status = 200, body = Vec<File>),
+        (status = 404, body = ErrorBody),
+    ),
+)]
+pub async fn list_post_files(
+    Path(post_id): Path<i64>,
+    State(state): State<Arc<AppState>>,
+    claims: Option<Extension<Claims>>,
+) -> Result<Json<Vec<File>>, AppError> {
+    let post = get_record(&post_id, &state.db().collection::<Resource>("posts")).await?;
+    ensure_visible(&state, &post, claims.as_deref()).await?;
+    Ok(Json(post.files))
+}
 
-    match fs::read_dir(&post_dir).await {
-        Ok(mut entries) => {
-            let mut files = Vec::new();
+// История версий файла, начиная с текущей
+#[utoipa::path(
+    get,
+    path = "/posts/{post_id}/files/{filename}/versions",
+    tag = "files",
+    params(("post_id" = i64, Path), ("filename" = String, Path)),
+    responses(
+        (status = 200, body = Vec<FileVersion>),
+        (status = 404, body = ErrorBody),
+    ),
+)]
+pub async fn list_file_versions(
+    Path((post_id, filename)): Path<(i64, String)>,
+    State(state): State<Arc<AppState>>,
+    claims: Option<Extension<Claims>>,
+) -> Result<Json<Vec<FileVersion>>, AppError> {
+    let post = get_record(&post_id, &state.db().collection::<Resource>("posts")).await?;
+    ensure_visible(&state, &post, claims.as_deref()).await?;
+    let file = post.files.iter().find(|v| v.filename == filename)
+        .ok_or_else(|| AppError::not_found("file", &filename))?;
 
-            while let Ok(Some(entry)) = entries.next_entry().await {
-                if let Ok(file_name) = entry.file_name().into_string() {
-                    files.push(file_name);
-                }
-            }
-            (StatusCode::OK, axum::Json(files)).into_response()
+    let mut versions: Vec<FileVersion> = vec![file.into()];
+    versions.extend(file.versions.iter().rev().cloned());
+    Ok(Json(versions))
+}
+
+// Восстановление прежней версии: её копия становится новой текущей версией,
+// так что история не теряется
+#[utoipa::path(
+    post,
+    path = "/posts/{post_id}/files/{filename}/versions/{version}/restore",
+    tag = "files",
+    params(("post_id" = i64, Path), ("filename" = String, Path), ("version" = i64, Path)),
+    responses(
+        (status = 200, body = File),
+        (status = 400, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+        (status = 413, body = ErrorBody),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn restore_file_version(
+    Path((post_id, filename, version)): Path<(i64, String, i64)>,
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Json<File>, AppError> {
+    let posts = state.db().collection::<Resource>("posts");
+    let mut post = get_record(&post_id, &posts).await?;
+    ensure_visible(&state, &post, Some(&claims)).await?;
+    ensure_owner(&state, &post, &claims, "restore files of this post").await?;
+    let index = post.files.iter().position(|v| v.filename == filename)
+        .ok_or_else(|| AppError::not_found("file", &filename))?;
+    let current = post.files[index].clone();
+    if version == current.version {
+        return Err(AppError::invalid("version", "is already the current version"));
+    }
+    let old = current.versions.iter().find(|v| v.version == version).cloned()
+        .ok_or_else(|| AppError::not_found("version", version))?;
+    if old.scan_status == ScanStatus::Infected {
+        return Err(AppError::Forbidden("an infected version cannot be restored".into()));
+    }
+
+    let post_dir = state.config.storage.uploads.join(post_id.to_string());
+    let part_path = part_path(&post_dir);
+    match fs::copy(version_path(&post_dir, &filename, version), &part_path).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == ErrorKind::NotFound => return Err(AppError::not_found("version", version)),
+        Err(e) => return Err(e.into()),
+    }
+
+    let user: User = get_record(&claims.sub, &state.db().collection("users")).await?;
+    let quota = allowance(&state.config.quotas, &user).map(|v| v.quota);
+    if let Err(e) = charge(&state.db(), &claims.sub, old.size, quota).await {
+        let _ = fs::remove_file(&part_path).await;
+        return Err(e);
+    }
+    if let Err(e) = replace_current(&post_dir, &part_path, &filename, Some(current.version)).await {
+        let _ = fs::remove_file(&part_path).await;
+        let _ = charge(&state.db(), &claims.sub, -old.size, None).await;
+        return Err(e.into());
+    }
+
+    let mut versions = current.versions.clone();
+    versions.push((&current).into());
+    post.files[index] = File {
+        filename: filename.clone(),
+        size: old.size,
+        preview_url: None,
+        text_extracted: false,
+        mime_type: old.mime_type,
+        sha256: old.sha256,
+        uploader: Some(claims.sub.clone()),
+        uploaded_at: Some(Utc::now()),
+        description: old.description,
+        scan_status: old.scan_status,
+        scan_details: old.scan_details,
+        version: current.version + 1,
+        versions,
+    };
+    update_record(&post_id, &post, &posts).await?;
+    tracing::info!(post = post_id, file = %filename, restored = version, "file version restored");
+
+    spawn_processing(&state, post_id, post_dir, vec![filename]);
+    Ok(Json(post.files[index].clone()))
+}
+
+// Превью файла поста; имя превью уникально, поэтому кешируется надолго
+#[utoipa::path(
+    get,
+    path = "/posts/{post_id}/previews/{preview}",
+    tag = "files",
+    params(("post_id" = i64, Path), ("preview" = String, Path)),
+    responses(
+        (status = 200, content_type = "image/jpeg", body = Vec<u8>),
+        (status = 404, body = ErrorBody),
+    ),
+)]
+pub async fn get_preview(
+    Path((post_id, preview)): Path<(i64, String)>,
+    State(state): State<Arc<AppState>>,
+    claims: Option<Extension<Claims>>,
+) -> Result<Response, AppError> {
+    let post = get_record(&post_id, &state.db().collection::<Resource>("posts")).await?;
+    ensure_visible(&state, &post, claims.as_deref()).await?;
+    let preview = sanitize_filename::sanitize(preview);
+    let path = previews_dir(&state.config.storage.uploads.join(post_id.to_string())).join(&preview);
+
+    match fs::read(&path).instrument(info_span!("fs.read", path = %path.display())).await {
+        Ok(data) => {
+            // Превью закрытых и неопубликованных постов не должны оседать в общих кешах
+            let cache = match (post.status, post.visibility) {
+                (PostStatus::Published, Visibility::Public | Visibility::Unlisted) => "public, max-age=31536000, immutable",
+                _ => "private, max-age=31536000, immutable",
+            };
+            let headers = [(CONTENT_TYPE, "image/jpeg"), (CACHE_CONTROL, cache)];
+            Ok((StatusCode::OK, headers, data).into_response())
         }
-        Err(_) => (
-            StatusCode::NOT_FOUND,
-            "Post directory not found".to_string(),
-        )
-            .into_response(),
+        Err(e) if e.kind() == ErrorKind::NotFound => Err(AppError::not_found("preview", preview)),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::extract::{FromRequest, Request};
+    use super::*;
+
+    const BOUNDARY: &str = "upload-boundary";
+
+    fn multipart(body: &'static str) -> Request {
+        Request::builder()
+            .method("POST")
+            .header(CONTENT_TYPE, format!("multipart/form-data; boundary={}", BOUNDARY))
+            .body(Body::from(body.replace('\n', "\r\n")))
+            .unwrap()
+    }
+
+    // Тело оборвалось на втором файле: первый сохранён целиком, а обрыв доходит
+    // до обработчика ошибкой, а не концом потока
+    #[tokio::test]
+    async fn truncated_body() {
+        let body = "--upload-boundary
+Content-Disposition: form-data; name=\"file\"; filename=\"first.txt\"
+
+complete
+--upload-boundary
+Content-Disposition: form-data; name=\"file\"; filename=\"second.txt\"
+
+cut off in the mid";
+        let mut multipart = Multipart::from_request(multipart(body), &()).await.unwrap();
+        let dir = std::env::temp_dir().join(format!("alexandria-upload-{}", uuid::Uuid::new_v4().simple()));
+        fs::create_dir_all(&dir).await.unwrap();
+
+        let first = multipart.next_field().await.unwrap().unwrap();
+        let metadata = save_field(first, &dir.join("first"), None).await.unwrap();
+        assert_eq!(metadata.size, "complete".len() as u64);
+
+        let second = match multipart.next_field().await {
+            Ok(Some(field)) => save_field(field, &dir.join("second"), None).await.map(|_| ()),
+            Ok(None) => panic!("truncated body read as complete"),
+            Err(e) => Err(e.into()),
+        };
+        assert!(second.is_err());
+        assert!(multipart.next_field().await.is_err());
+
+        fs::remove_dir_all(&dir).await.unwrap();
     }
 }
@@ -0,0 +1,107 @@
+use std::path::Path;
+use std::sync::Arc;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::Json;
+use bson::doc;
+use serde::Serialize;
+use tokio::fs;
+use utoipa::ToSchema;
+use crate::AppState;
+
+const PROBE_FILE: &str = ".readyz";
+
+#[derive(Serialize, ToSchema)]
+pub struct Check {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Readiness {
+    ready: bool,
+    mongodb: Check,
+    uploads: Check,
+    disk: Check,
+}
+
+impl From<Result<(), String>> for Check {
+    fn from(value: Result<(), String>) -> Self {
+        match value {
+            Ok(()) => Check { ok: true, error: None },
+            Err(e) => Check { ok: false, error: Some(e) },
+        }
+    }
+}
+
+async fn check_mongodb(state: &AppState) -> Result<(), String> {
+    state.db().run_command(doc! {"ping": 1}).await.map(|_| ()).map_err(|e| e.to_string())
+}
+
+// Записываем и удаляем пробный файл, чтобы убедиться, что каталог доступен на запись
+async fn check_uploads(uploads: &Path) -> Result<(), String> {
+    let probe = uploads.join(PROBE_FILE);
+    fs::write(&probe, b"ok").await.map_err(|e| e.to_string())?;
+    fs::remove_file(&probe).await.map_err(|e| e.to_string())
+}
+
+async fn check_disk(uploads: &Path, min_free_bytes: u64) -> Result<(), String> {
+    let uploads = uploads.to_path_buf();
+    let free = tokio::task::spawn_blocking(move || fs2::available_space(uploads))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())?;
+    if free < min_free_bytes {
+        return Err(format!("{} bytes free, {} required", free, min_free_bytes));
+    }
+    Ok(())
+}
+
+// Процесс жив и обрабатывает запросы
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "health",
+    responses((status = 200, body = String)),
+)]
+pub async fn healthz() -> &'static str {
+    "ok"
+}
+
+// Сервер готов принимать трафик: база доступна, каталог загрузок пишется, место на диске есть
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "health",
+    responses(
+        (status = 200, body = Readiness),
+        (status = 503, body = Readiness),
+    ),
+)]
+pub async fn readyz(State(state): State<Arc<AppState>>) -> (StatusCode, Json<Readiness>) {
+    let uploads = &state.config.storage.uploads;
+    let (mongodb, writable, disk) = tokio::join!(
+        check_mongodb(&state),
+        check_uploads(uploads),
+        check_disk(uploads, state.config.storage.min_free_bytes),
+    );
+
+    let readiness = Readiness {
+        ready: mongodb.is_ok() && writable.is_ok() && disk.is_ok(),
+        mongodb: mongodb.into(),
+        uploads: writable.into(),
+        disk: disk.into(),
+    };
+    if !readiness.ready {
+        tracing::warn!(
+            mongodb = readiness.mongodb.error,
+            uploads = readiness.uploads.error,
+            disk = readiness.disk.error,
+            "not ready",
+        );
+    }
+
+    let status = if readiness.ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, Json(readiness))
+}
@@ -0,0 +1,113 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use axum::extract::{ConnectInfo, Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::{Extension, Json};
+use bson::doc;
+use chrono::{Duration, SubsecRound, Utc};
+use mongodb::Collection;
+use crate::AppState;
+use crate::access::{ensure_owner, ensure_visible};
+use crate::db::get_record;
+use crate::error::{AppError, ErrorBody};
+use crate::links::{client_ip, link_url, LINKS_COLLECTION};
+use crate::structures::{Claims, CreateLink, DownloadLink, Resource, SignedLink};
+
+// Подписанная ссылка на скачивание файла без токена
+#[utoipa::path(
+    post,
+    path = "/posts/{post_id}/files/{filename}/links",
+    tag = "files",
+    params(("post_id" = i64, Path), ("filename" = String, Path)),
+    request_body = CreateLink,
+    responses(
+        (status = 200, body = SignedLink),
+        (status = 400, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn create_link(
+    Path((post_id, filename)): Path<(i64, String)>,
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(payload): Json<CreateLink>,
+) -> Result<Json<SignedLink>, AppError> {
+    let post: Resource = get_record(&post_id, &state.db().collection("posts")).await?;
+    ensure_visible(&state, &post, Some(&claims)).await?;
+    ensure_owner(&state, &post, &claims, "share files of this post").await?;
+
+    let file = post.files.iter().find(|v| v.filename == filename)
+        .ok_or_else(|| AppError::not_found("file", &filename))?;
+    if let Some(version) = payload.version {
+        file.find_version(version).ok_or_else(|| AppError::not_found("version", version))?;
+    }
+
+    let config = &state.config.links;
+    let ttl = payload.expires_in_secs.unwrap_or(config.default_ttl_secs);
+    if ttl == 0 || ttl > config.max_ttl_secs {
+        return Err(AppError::invalid("expires_in_secs", format!("must be between 1 and {}", config.max_ttl_secs)));
+    }
+    if payload.max_downloads.is_some_and(|v| v <= 0) {
+        return Err(AppError::invalid("max_downloads", "must be positive"));
+    }
+    let ip = match (payload.ip, payload.bind_ip) {
+        (Some(ip), _) => Some(ip.parse::<IpAddr>().map_err(|_| AppError::invalid("ip", "not an IP address"))?),
+        (None, true) => Some(client_ip(&state.config, &headers, peer)),
+        (None, false) => None,
+    };
+
+    let link = DownloadLink {
+        id: uuid::Uuid::new_v4().simple().to_string(),
+        post: post_id,
+        filename: file.filename.clone(),
+        version: payload.version,
+        // Срок в ссылке задан в секундах, дробная часть отбрасывается
+        expires_at: (Utc::now() + Duration::seconds(ttl as i64)).trunc_subsecs(0),
+        max_downloads: payload.max_downloads,
+        downloads: 0,
+        ip: ip.map(|v| v.to_string()),
+        created_by: claims.sub.clone(),
+    };
+    state.db().collection::<DownloadLink>(LINKS_COLLECTION).insert_one(&link).await?;
+    tracing::info!(post = post_id, file = %filename, link = %link.id, "download link created");
+
+    Ok(Json(SignedLink {
+        url: link_url(&state.config, &link),
+        id: link.id,
+        expires_at: link.expires_at,
+        max_downloads: link.max_downloads,
+        ip: link.ip,
+    }))
+}
+
+// Отзыв ссылки до истечения срока
+#[utoipa::path(
+    delete,
+    path = "/links/{link_id}",
+    tag = "files",
+    params(("link_id" = String, Path)),
+    responses(
+        (status = 204),
+        (status = 403, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn revoke_link(
+    Path(link_id): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+) -> Result<StatusCode, AppError> {
+    let links: Collection<DownloadLink> = state.db().collection(LINKS_COLLECTION);
+    let link = get_record(&link_id, &links).await?;
+    if link.created_by != claims.sub {
+        let post: Resource = get_record(&link.post, &state.db().collection("posts")).await?;
+        ensure_owner(&state, &post, &claims, "share files of this post").await?;
+    }
+    links.delete_one(doc! {"_id": &link_id}).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -1,3 +1,9 @@
 pub mod posts;
 pub mod user;
-pub mod files;
\ No newline at end of file
+pub mod files;
+pub mod feed;
+pub mod tags;
+pub mod health;
+pub mod search;
+pub mod archive;
+pub mod links;
\ No newline at end of file
@@ -1,35 +1,59 @@
 use std::sync::Arc;
-use axum::extract::{State};
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
 use axum::{debug_handler, Extension, Json};
 use axum_extra::extract::Query;
 use bson::doc;
+use chrono::Utc;
 use futures_util::TryStreamExt;
 use mongodb::Collection;
 use serde::Deserialize;
+use utoipa::IntoParams;
 use crate::AppState;
+use crate::access::{ensure_visible, listed_filter};
+use crate::bibliography::BibliographyQuery;
 use crate::db::{create_record, get_record, update_record};
-use crate::error::AppError;
-use crate::structures::{Claims, CreateResource, RatedPost, Rating, Resource, SendResource, User};
+use crate::error::{AppError, ErrorBody};
+use crate::metrics::METRICS;
+use crate::structures::{Claims, CreateResource, PostStatus, PublishPost, RatedPost, Resource, SendResource, SetVisibility, User};
 
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct GetParams {
     posts: Vec<i64>,
+    // Принимается ради совместимости с клиентами, отбор по ключевым словам здесь не делается
+    #[allow(dead_code)]
     keywords: Vec<String>,
 }
 
+#[utoipa::path(
+    get,
+    path = "/get_posts",
+    tag = "posts",
+    params(GetParams, BibliographyQuery),
+    responses(
+        (status = 200, description = "Requested posts, or the latest ten matching the bibliographic filters when `posts` is 0", body = Vec<SendResource>),
+        (status = 400, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    ),
+    security(("bearer" = [])),
+)]
 pub async fn get_posts(
     State(state): State<Arc<AppState>>,
     Extension(claims): Extension<Claims>,
-    Query(mut params): Query<GetParams>
+    Query(mut params): Query<GetParams>,
+    Query(bibliography): Query<BibliographyQuery>,
 ) -> Result<Json<Vec<SendResource>>, AppError> {
-    let posts: Collection<Resource> = state.client.database("alexandria").collection("posts");
-    let user: User = get_record(&claims.sub, &state.client.database("alexandria").collection("users")).await?;
+    let posts: Collection<Resource> = state.db().collection("posts");
+    let user: User = get_record(&claims.sub, &state.db().collection("users")).await?;
     
     let mut result = vec![];
     if params.posts.pop() == Some(0) {
-        let mut cursor = posts.find(doc! {})
-            .sort(doc! {"upload_time": -1}).await
-            .map_err(|_| AppError::InternalServerError)?;
+        let mut filter = listed_filter(Some(&claims));
+        filter.insert("hidden", doc! {"$ne": true});
+        filter.extend(bibliography.filter()?);
+        let mut cursor = posts.find(filter)
+            .sort(doc! {"upload_time": -1}).await?;
         for _ in 1..=10 {
             if let Ok(Some(v)) = cursor.try_next().await {
                 result.push(v);
@@ -37,51 +61,161 @@ pub async fn get_posts(
         }
     } else {
         for post_id in params.posts {
-            result.push(get_record(&post_id, &posts).await?);
+            let post = get_record(&post_id, &posts).await?;
+            ensure_visible(&state, &post, Some(&claims)).await?;
+            result.push(post);
         }
     }
     
     let mut posts = vec![];
     
     for post in result.into_iter() {
-        if let Some(pt) = user.rated.iter().find(|x| x.post == post.id) {
-            posts.push(post.into_send_resource(pt.clone().rating));
-        } else {
-            posts.push(post.into_send_resource(Rating::None));
-        }
+        let rating = user.get_rating(post.id);
+        posts.push(post.into_send_resource(rating));
     }
     
     Ok(Json(posts))
 }
 
+#[utoipa::path(
+    post,
+    path = "/create_post",
+    tag = "posts",
+    request_body = CreateResource,
+    responses(
+        (status = 200, description = "Id of the new post", body = i64),
+        (status = 404, body = ErrorBody),
+    ),
+    security(("bearer" = [])),
+)]
 #[debug_handler]
 pub async fn create_post(
     State(state): State<Arc<AppState>>,
     Extension(claims): Extension<Claims>,
     Json(payload): Json<CreateResource>,
 ) -> Result<Json<i64>, AppError> {
-    let db = state.client.database("alexandria");
+    let db = state.db();
     let posts: Collection<Resource> = db.collection("posts");
     let user: User = get_record(&claims.sub, &db.collection("users")).await?;
 
-    match create_record(&payload.into_resource(user.id, user.username, &state.id_gen).await?, &posts).await {
+    match create_record(&payload.into_resource(user.id, user.username, &state.id_gen, &state.taxonomy).await?, &posts).await {
         Ok(v) => Ok(Json(v.inserted_id.as_i64().unwrap())),
         Err(e) => Err(e)
     }
 }
 
+#[utoipa::path(
+    post,
+    path = "/rate_post",
+    tag = "posts",
+    request_body = RatedPost,
+    responses(
+        (status = 200, body = Resource),
+        (status = 404, body = ErrorBody),
+    ),
+    security(("bearer" = [])),
+)]
 pub async fn rate_post(
     State(state): State<Arc<AppState>>,
-    Extension(user): Extension<Claims>,
+    Extension(claims): Extension<Claims>,
     Json(payload): Json<RatedPost>,
 ) -> Result<Json<Resource>, AppError> {
-    let db = state.client.database("alexandria");
+    let db = state.db();
     let posts: Collection<Resource> = db.collection("posts");
     let mut post = get_record(&payload.post, &posts).await?;
-    let mut user: User = get_record(&user.sub, &db.collection("users")).await?;
+    ensure_visible(&state, &post, Some(&claims)).await?;
+    let mut user: User = get_record(&claims.sub, &db.collection("users")).await?;
+    METRICS.ratings.with_label_values(&[&format!("{:?}", payload.rating)]).inc();
     post.rating += user.add_rated(payload);
     update_record(&post.id, &post, &posts).await?;
     update_record(&user.id, &user, &db.collection("users")).await?;
 
     Ok(Json(post))
-}
\ No newline at end of file
+}
+
+// Смена видимости поста: автору и администраторам
+#[utoipa::path(
+    put,
+    path = "/posts/{post_id}/visibility",
+    tag = "posts",
+    params(("post_id" = i64, Path)),
+    request_body = SetVisibility,
+    responses(
+        (status = 204),
+        (status = 403, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn set_visibility(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Path(post_id): Path<i64>,
+    Json(payload): Json<SetVisibility>,
+) -> Result<StatusCode, AppError> {
+    let db = state.db();
+    let posts: Collection<Resource> = db.collection("posts");
+    let post = get_record(&post_id, &posts).await?;
+    ensure_visible(&state, &post, Some(&claims)).await?;
+    if post.author() != claims.sub {
+        let user: User = get_record(&claims.sub, &db.collection("users")).await?;
+        if !user.is_admin() {
+            return Err(AppError::Forbidden("only the author can change the visibility of a post".into()));
+        }
+    }
+
+    posts.update_one(doc! {"_id": post_id}, doc! {"$set": {"visibility": payload.visibility}}).await?;
+    tracing::info!(post = post_id, visibility = ?payload.visibility, "post visibility changed");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Публикация черновика сразу или в указанное время. Черновик виден только автору,
+// поэтому и опубликовать его может только автор
+#[utoipa::path(
+    post,
+    path = "/posts/{post_id}/publish",
+    tag = "posts",
+    params(("post_id" = i64, Path)),
+    request_body = PublishPost,
+    responses(
+        (status = 200, description = "The post after publishing or scheduling", body = SendResource),
+        (status = 400, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn publish_post(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Path(post_id): Path<i64>,
+    payload: Option<Json<PublishPost>>,
+) -> Result<Json<SendResource>, AppError> {
+    let db = state.db();
+    let posts: Collection<Resource> = db.collection("posts");
+    let mut post = get_record(&post_id, &posts).await?;
+    ensure_visible(&state, &post, Some(&claims)).await?;
+    if post.author() != claims.sub {
+        return Err(AppError::Forbidden("only the author can publish a post".into()));
+    }
+    if post.status == PostStatus::Published {
+        return Err(AppError::BadRequest("post is already published".into()));
+    }
+
+    let Json(payload) = payload.unwrap_or_default();
+    match payload.publish_at.filter(|v| *v > Utc::now()) {
+        Some(publish_at) => {
+            post.status = PostStatus::Scheduled;
+            post.publish_at = Some(publish_at);
+            tracing::info!(post = post_id, %publish_at, "post scheduled");
+        }
+        None => {
+            post.publish();
+            tracing::info!(post = post_id, "post published");
+        }
+    }
+    update_record(&post.id, &post, &posts).await?;
+
+    let user: User = get_record(&claims.sub, &db.collection("users")).await?;
+    let rating = user.get_rating(post.id);
+    Ok(Json(post.into_send_resource(rating)))
+}
@@ -0,0 +1,172 @@
+use std::sync::Arc;
+use axum::extract::{Query, State};
+use axum::{Extension, Json};
+use bson::{doc, Document};
+use futures_util::TryStreamExt;
+use mongodb::Collection;
+use serde::Deserialize;
+use utoipa::IntoParams;
+use crate::AppState;
+use crate::access::listed_filter;
+use crate::error::{AppError, ErrorBody};
+use crate::structures::{Claims, FileText, SearchHit, TextLocation};
+
+const SEARCH_PAGE: i64 = 20;
+const SEARCH_MAX_PAGE: i64 = 50;
+// Сколько мест попадания показывать на файл и сколько символов контекста вокруг
+const MAX_LOCATIONS: usize = 20;
+const SNIPPET_CONTEXT: usize = 60;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SearchParams {
+    q: String,
+    limit: Option<i64>,
+}
+
+fn floor_boundary(text: &str, mut index: usize) -> usize {
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+fn ceil_boundary(text: &str, mut index: usize) -> usize {
+    while !text.is_char_boundary(index) {
+        index += 1;
+    }
+    index
+}
+
+fn snippet(text: &str, at: usize, len: usize) -> String {
+    let start = floor_boundary(text, at.saturating_sub(SNIPPET_CONTEXT));
+    let end = ceil_boundary(text, (at + len + SNIPPET_CONTEXT).min(text.len()));
+    text[start..end].split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// Места, где встречается хотя бы одно из слов запроса; для PDF с номером страницы
+fn locations(text: &FileText, terms: &[String]) -> Vec<TextLocation> {
+    let mut found = vec![];
+    for (i, page) in text.pages.iter().enumerate() {
+        let lower = page.to_lowercase();
+        // Если регистр сменил длину строки, берём контекст из приведённого текста
+        let source = if lower.len() == page.len() { page.as_str() } else { lower.as_str() };
+        let hit = terms.iter()
+            .filter_map(|term| lower.find(term.as_str()).map(|at| (at, term.len())))
+            .min();
+        if let Some((at, len)) = hit {
+            found.push(TextLocation {
+                page: text.paged.then_some(i + 1),
+                snippet: snippet(source, at, len),
+            });
+        }
+        if found.len() == MAX_LOCATIONS {
+            break;
+        }
+    }
+    found
+}
+
+// Полнотекстовый поиск по содержимому загруженных файлов
+#[utoipa::path(
+    get,
+    path = "/search",
+    tag = "search",
+    params(SearchParams),
+    responses(
+        (status = 200, body = Vec<SearchHit>),
+        (status = 400, body = ErrorBody),
+    ),
+)]
+pub async fn search(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<SearchParams>,
+    claims: Option<Extension<Claims>>,
+) -> Result<Json<Vec<SearchHit>>, AppError> {
+    let query = params.q.trim();
+    if query.is_empty() {
+        return Err(AppError::invalid("q", "must not be empty"));
+    }
+    let limit = params.limit.unwrap_or(SEARCH_PAGE).clamp(1, SEARCH_MAX_PAGE);
+
+    let db = state.db();
+    let texts: Collection<Document> = db.collection("file_texts");
+
+    // Видимость поста проверяется в самом запросе до limit, иначе недоступные
+    // посты съедали бы место на странице. Скрытые посты в выдачу не попадают
+    let mut visible = listed_filter(claims.as_deref());
+    visible.insert("hidden", doc! {"$ne": true});
+    let pipeline = vec![
+        doc! {"$match": {"$text": {"$search": query}}},
+        doc! {"$addFields": {"score": {"$meta": "textScore"}}},
+        doc! {"$lookup": {
+            "from": "posts",
+            "let": {"post": "$post"},
+            "pipeline": [
+                {"$match": {"$and": [{"$expr": {"$eq": ["$_id", "$$post"]}}, visible]}},
+                {"$project": {"title": 1}},
+            ],
+            "as": "matched",
+        }},
+        doc! {"$match": {"matched": {"$ne": []}}},
+        doc! {"$sort": {"score": -1}},
+        doc! {"$limit": limit},
+        doc! {"$addFields": {"title": {"$arrayElemAt": ["$matched.title", 0]}}},
+        doc! {"$project": {"matched": 0}},
+    ];
+    let matches: Vec<Document> = texts.aggregate(pipeline).await?.try_collect().await?;
+
+    let terms: Vec<String> = query
+        .split_whitespace()
+        .map(|v| v.trim_matches('"').to_lowercase())
+        .filter(|v| !v.is_empty() && !v.starts_with('-'))
+        .collect();
+
+    let mut hits = vec![];
+    for document in matches {
+        let score = document.get_f64("score").unwrap_or_default();
+        let title = document.get_str("title").unwrap_or_default().to_string();
+        let text: FileText = bson::from_document(document)?;
+        hits.push(SearchHit {
+            post: text.post,
+            title,
+            filename: text.filename.clone(),
+            score,
+            locations: locations(&text, &terms),
+        });
+    }
+
+    Ok(Json(hits))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snippet_multibyte() {
+        // Границы контекста попадают на середину двухбайтовых символов
+        let text = format!("{}искомое{}", "я".repeat(45), "ж".repeat(45));
+        let at = text.find("искомое").unwrap();
+        let found = snippet(&text, at, "искомое".len());
+        assert_eq!(found, format!("{}искомое{}", "я".repeat(30), "ж".repeat(30)));
+
+        // Четырёхбайтовые символы: граница сдвигается наружу, а не режет символ
+        let text = format!("{}word{}", "😀".repeat(20), "😀".repeat(20));
+        let at = text.find("word").unwrap();
+        assert_eq!(snippet(&text, at, 4), format!("{}word{}", "😀".repeat(15), "😀".repeat(15)));
+    }
+
+    #[test]
+    fn snippet_at_text_edges() {
+        assert_eq!(snippet("ёлка", 0, "ёлка".len()), "ёлка");
+        let text = format!("{} конец", "ы".repeat(100));
+        let at = text.find("конец").unwrap();
+        assert_eq!(snippet(&text, at, "конец".len()), format!("{} конец", "ы".repeat(30)));
+    }
+
+    #[test]
+    fn snippet_collapses_whitespace() {
+        assert_eq!(snippet("one\n\n  two\tthree", 5, 3), "one two three");
+    }
+}
@@ -0,0 +1,191 @@
+use std::sync::Arc;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::{Extension, Json};
+use bson::{doc, Document};
+use futures_util::TryStreamExt;
+use mongodb::Collection;
+use serde::Deserialize;
+use utoipa::IntoParams;
+use crate::AppState;
+use crate::access::listed_filter;
+use crate::db::{create_record, find_record};
+use crate::endpoints::user::require_admin;
+use crate::error::{AppError, ErrorBody};
+use crate::structures::{Claims, CreateAliases, Resource, TagAlias, TagCount, User};
+use crate::tags::{escape_regex, normalize_keyword};
+
+const TAGS_PAGE: i64 = 50;
+const AUTOCOMPLETE_PAGE: i64 = 10;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TagsParams {
+    limit: Option<i64>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AutocompleteParams {
+    prefix: String,
+    limit: Option<i64>,
+}
+
+async fn count_tags(
+    posts: &Collection<Resource>,
+    filter: Document,
+    limit: i64,
+) -> Result<Vec<TagCount>, AppError> {
+    // Теги считаются только по постам, которые видны в списках анонимно
+    let mut listed = listed_filter(None);
+    listed.insert("hidden", doc! {"$ne": true});
+    let pipeline = vec![
+        doc! {"$match": listed},
+        doc! {"$unwind": "$keywords"},
+        doc! {"$match": filter},
+        doc! {"$group": {"_id": "$keywords", "count": {"$sum": 1}}},
+        doc! {"$sort": {"count": -1, "_id": 1}},
+        doc! {"$limit": limit},
+    ];
+
+    let documents: Vec<Document> = posts.aggregate(pipeline).await?
+        .try_collect().await?;
+
+    documents.into_iter()
+        .map(|v| Ok(bson::from_document(v)?))
+        .collect()
+}
+
+// Все теги с количеством постов
+#[utoipa::path(
+    get,
+    path = "/tags",
+    tag = "tags",
+    params(TagsParams),
+    responses((status = 200, body = Vec<TagCount>)),
+)]
+pub async fn get_tags(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<TagsParams>,
+) -> Result<Json<Vec<TagCount>>, AppError> {
+    let posts = state.db().collection("posts");
+    let limit = params.limit.unwrap_or(TAGS_PAGE).clamp(1, 1000);
+
+    Ok(Json(count_tags(&posts, doc! {}, limit).await?))
+}
+
+// Подсказки тегов по префиксу
+#[utoipa::path(
+    get,
+    path = "/tags/autocomplete",
+    tag = "tags",
+    params(AutocompleteParams),
+    responses(
+        (status = 200, body = Vec<TagCount>),
+        (status = 400, body = ErrorBody),
+    ),
+)]
+pub async fn autocomplete_tags(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<AutocompleteParams>,
+) -> Result<Json<Vec<TagCount>>, AppError> {
+    let posts = state.db().collection("posts");
+    let prefix = normalize_keyword(&params.prefix);
+    if prefix.is_empty() {
+        return Err(AppError::invalid("prefix", "must not be empty"));
+    }
+    let limit = params.limit.unwrap_or(AUTOCOMPLETE_PAGE).clamp(1, 100);
+    let filter = doc! {"keywords": {"$regex": format!("^{}", escape_regex(&prefix))}};
+
+    Ok(Json(count_tags(&posts, filter, limit).await?))
+}
+
+#[utoipa::path(
+    get,
+    path = "/tags/aliases",
+    tag = "tags",
+    responses((status = 200, body = Vec<TagAlias>)),
+)]
+pub async fn list_aliases(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<TagAlias>>, AppError> {
+    let aliases = state.taxonomy.aliases().find(doc! {})
+        .sort(doc! {"tag": 1, "_id": 1}).await?
+        .try_collect().await?;
+
+    Ok(Json(aliases))
+}
+
+// Добавление синонимов тега (только для администраторов)
+#[utoipa::path(
+    post,
+    path = "/tags/aliases",
+    tag = "tags",
+    request_body = CreateAliases,
+    responses(
+        (status = 201),
+        (status = 403, body = ErrorBody),
+        (status = 409, body = ErrorBody),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn create_aliases(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Json(payload): Json<CreateAliases>,
+) -> Result<StatusCode, AppError> {
+    require_admin(&state, &claims).await?;
+    let db = state.db();
+    let posts: Collection<Resource> = db.collection("posts");
+    let users: Collection<User> = db.collection("users");
+
+    let tag = state.taxonomy.resolve(&payload.tag).await?.ok_or(AppError::invalid("tag", "must not be empty"))?;
+
+    for alias in payload.aliases {
+        let alias = normalize_keyword(&alias);
+        if alias.is_empty() || alias == tag {
+            continue;
+        }
+        if find_record(&alias, state.taxonomy.aliases()).await?.is_some() {
+            return Err(AppError::conflict("tag_alias", alias));
+        }
+        create_record(&TagAlias { alias: alias.clone(), tag: tag.clone() }, state.taxonomy.aliases()).await?;
+        state.taxonomy.aliases().update_many(doc! {"tag": &alias}, doc! {"$set": {"tag": &tag}}).await?;
+
+        // Переписываем уже существующие посты и подписки на канонический тег
+        posts.update_many(doc! {"keywords": &alias}, doc! {"$addToSet": {"keywords": &tag}}).await?;
+        posts.update_many(doc! {"keywords": &alias}, doc! {"$pull": {"keywords": &alias}}).await?;
+        users.update_many(doc! {"followed_keywords": &alias}, doc! {"$addToSet": {"followed_keywords": &tag}}).await?;
+        users.update_many(doc! {"followed_keywords": &alias}, doc! {"$pull": {"followed_keywords": &alias}}).await?;
+    }
+
+    Ok(StatusCode::CREATED)
+}
+
+#[utoipa::path(
+    delete,
+    path = "/tags/aliases/{alias}",
+    tag = "tags",
+    params(("alias" = String, Path)),
+    responses(
+        (status = 204),
+        (status = 403, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn delete_alias(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Path(alias): Path<String>,
+) -> Result<StatusCode, AppError> {
+    require_admin(&state, &claims).await?;
+
+    let alias = normalize_keyword(&alias);
+    let result = state.taxonomy.aliases().delete_one(doc! {"_id": &alias}).await?;
+    if result.deleted_count == 0 {
+        return Err(AppError::not_found("tag_alias", alias));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -1,28 +1,49 @@
 use std::collections::HashMap;
 use std::sync::Arc;
-use axum::extract::{Query, State};
+use axum::extract::{Path, Query, State};
 use axum::http::StatusCode;
 use axum::{Extension, Json};
+use bson::doc;
 use mongodb::Collection;
 use crate::AppState;
-use crate::db::{create_record, get_record};
-use crate::error::AppError;
+use crate::db::{create_record, find_record, get_record};
+use crate::error::{AppError, ErrorBody};
 use crate::hash::{generate_token, hash_password, verify_password};
-use crate::structures::{Claims, User};
+use crate::metrics::METRICS;
+use crate::quota::allowance;
+use crate::structures::{Claims, SetQuota, Usage, User};
 
+#[utoipa::path(
+    post,
+    path = "/register",
+    tag = "users",
+    params(
+        ("email" = String, Query),
+        ("username" = String, Query),
+        ("password" = String, Query),
+    ),
+    responses(
+        (status = 201),
+        (status = 400, body = ErrorBody),
+        (status = 409, body = ErrorBody),
+    ),
+)]
 pub async fn register(
     State(state): State<Arc<AppState>>,
     Query(params): Query<HashMap<String, String>>
 ) -> Result<StatusCode, AppError> {
-    let database = state.client.database("alexandria");
+    let database = state.db();
     let users: Collection<User> = database.collection("users");
 
-    let password = params.get("password").ok_or(AppError::BadRequest)?;
-    let username = params.get("username").ok_or(AppError::BadRequest)?;
-    let email = params.get("email").ok_or(AppError::BadRequest)?;
+    let password = params.get("password").ok_or(AppError::MissingParameter("password".into()))?;
+    let username = params.get("username").ok_or(AppError::MissingParameter("username".into()))?;
+    let email = params.get("email").ok_or(AppError::MissingParameter("email".into()))?;
 
-    if get_record(email, &users).await.is_ok() {
-        return Err(AppError::Conflict);
+    if find_record(email, &users).await?.is_some() {
+        return Err(AppError::conflict("user", email));
+    }
+    if users.find_one(doc! {"username": username}).await?.is_some() {
+        return Err(AppError::conflict("username", username));
     }
 
     let result = create_record(&User::new(
@@ -37,29 +58,133 @@ pub async fn register(
     }
 }
 
+#[utoipa::path(
+    post,
+    path = "/login",
+    tag = "users",
+    params(
+        ("id" = String, Query, description = "User email"),
+        ("password" = String, Query),
+    ),
+    responses(
+        (status = 200, description = "A `[user, token]` pair", body = Vec<Object>),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+    ),
+)]
 pub async fn login(
     State(state): State<Arc<AppState>>,
     Query(params): Query<HashMap<String, String>>
 ) -> Result<Json<(User, String)>, AppError>{
-    let database = state.client.database("alexandria");
+    let database = state.db();
     let users: Collection<User> = database.collection("users");
 
-    let user_id = params.get("id").ok_or(AppError::BadRequest)?;
-    let password = params.get("password").ok_or(AppError::BadRequest)?;
+    let user_id = params.get("id").ok_or(AppError::MissingParameter("id".into()))?;
+    let password = params.get("password").ok_or(AppError::MissingParameter("password".into()))?;
 
     match get_record(user_id, &users).await {
         Ok(user) => {
+            if user.disabled {
+                return Err(AppError::Forbidden("account disabled".into()));
+            }
             if verify_password(password.to_owned(), user.password_hash.clone()) {
-                return Ok(Json((user, generate_token(user_id.to_string())?)))
+                METRICS.logins.with_label_values(&["success"]).inc();
+                return Ok(Json((user, generate_token(user_id.to_string(), &state.config.jwt)?)))
             }
-            Err(AppError::NotAuthorized)
+            METRICS.logins.with_label_values(&["failure"]).inc();
+            Err(AppError::NotAuthorized("wrong password".into()))
         },
-        Err(e) => Err(e),
+        Err(e) => {
+            METRICS.logins.with_label_values(&["failure"]).inc();
+            Err(e)
+        }
     }
 }
 
+#[utoipa::path(
+    put,
+    path = "/update_token",
+    tag = "users",
+    responses(
+        (status = 200, description = "A fresh token", body = String),
+        (status = 403, body = ErrorBody),
+    ),
+    security(("bearer" = [])),
+)]
 pub async fn update_token(
+    State(state): State<Arc<AppState>>,
     Extension(claims): Extension<Claims>
 ) -> Result<String, AppError> {
-    generate_token(claims.sub)
-}
\ No newline at end of file
+    let user: User = get_record(&claims.sub, &state.db().collection("users")).await?;
+    if user.disabled {
+        return Err(AppError::Forbidden("account disabled".into()));
+    }
+    generate_token(claims.sub, &state.config.jwt)
+}
+
+pub async fn require_admin(state: &AppState, claims: &Claims) -> Result<User, AppError> {
+    let user: User = get_record(&claims.sub, &state.db().collection("users")).await?;
+    if !user.is_admin() {
+        return Err(AppError::Forbidden("administrator role required".into()));
+    }
+    Ok(user)
+}
+
+// Занятое место и квота текущего пользователя
+#[utoipa::path(
+    get,
+    path = "/me/usage",
+    tag = "users",
+    responses(
+        (status = 200, body = Usage),
+        (status = 401, body = ErrorBody),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn get_usage(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Json<Usage>, AppError> {
+    let user: User = get_record(&claims.sub, &state.db().collection("users")).await?;
+    let allowance = allowance(&state.config.quotas, &user);
+    Ok(Json(Usage {
+        used_bytes: user.used_bytes.max(0) as u64,
+        quota_bytes: allowance.map(|v| v.quota),
+        remaining_bytes: allowance.map(|v| v.remaining()),
+    }))
+}
+
+// Индивидуальная квота пользователя вместо квоты его роли
+#[utoipa::path(
+    put,
+    path = "/users/{user_id}/quota",
+    tag = "users",
+    params(("user_id" = String, Path, description = "User email")),
+    request_body = SetQuota,
+    responses(
+        (status = 204),
+        (status = 400, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn set_quota(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Path(user_id): Path<String>,
+    Json(body): Json<SetQuota>,
+) -> Result<StatusCode, AppError> {
+    require_admin(&state, &claims).await?;
+    if body.quota_bytes.is_some_and(|v| v < 0) {
+        return Err(AppError::invalid("quota_bytes", "must not be negative"));
+    }
+
+    let users: Collection<User> = state.db().collection("users");
+    let result = users.update_one(doc! {"_id": &user_id}, doc! {"$set": {"quota_bytes": body.quota_bytes}}).await?;
+    if result.matched_count == 0 {
+        return Err(AppError::not_found("user", user_id));
+    }
+    tracing::info!(user = %user_id, quota = ?body.quota_bytes, "quota changed");
+    Ok(StatusCode::NO_CONTENT)
+}
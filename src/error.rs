@@ -1,40 +1,219 @@
 use std::fmt::{Display};
 
-use axum::http::StatusCode;
+use axum::extract::multipart::MultipartError;
+use axum::http::header::RETRY_AFTER;
+use axum::http::{HeaderValue, StatusCode};
 use axum::response::{IntoResponse, Response};
-use serde::{Deserialize, Serialize};
+use axum::Json;
+use jsonwebtoken::errors::ErrorKind as JwtErrorKind;
+use mongodb::error::WriteFailure;
+use serde::Serialize;
+use serde_json::{json, Value};
+use utoipa::ToSchema;
+use crate::db::is_transient;
+use crate::layers::request_id::current_request_id;
 
-#[derive(Debug, Serialize, Deserialize)]
+const RETRY_AFTER_SECONDS: u32 = 5;
+const DUPLICATE_KEY: i32 = 11000;
+
+#[derive(Debug)]
 pub enum AppError {
-    BadRequest,
-    Conflict,
-    InternalServerError,
-    NotFound,
-    NotAuthorized,
+    BadRequest(String),
+    MissingParameter(String),
+    InvalidField { field: String, reason: String },
+    Conflict { entity: String, id: String },
+    NotFound { entity: String, id: String },
+    NotAuthorized(String),
+    Forbidden(String),
+    QuotaExceeded { used: u64, quota: u64, requested: u64 },
+    Token(String),
+    Database(String),
+    Unavailable(String),
+    Io(String),
+    InternalServerError(String),
+}
+
+// Тело ответа с ошибкой
+#[derive(Serialize, ToSchema)]
+pub struct ErrorBody {
+    code: String,
+    message: String,
+    #[schema(value_type = Option<Object>)]
+    details: Option<Value>,
+    request_id: Option<String>,
+}
+
+impl AppError {
+    pub fn not_found(entity: &str, id: impl ToString) -> AppError {
+        AppError::NotFound { entity: entity.to_string(), id: id.to_string() }
+    }
+
+    pub fn conflict(entity: &str, id: impl ToString) -> AppError {
+        AppError::Conflict { entity: entity.to_string(), id: id.to_string() }
+    }
+
+    pub fn invalid(field: &str, reason: impl ToString) -> AppError {
+        AppError::InvalidField { field: field.to_string(), reason: reason.to_string() }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::BadRequest(_)
+            | AppError::MissingParameter(_)
+            | AppError::InvalidField { .. } => StatusCode::BAD_REQUEST,
+            AppError::Conflict { .. } => StatusCode::CONFLICT,
+            AppError::NotFound { .. } => StatusCode::NOT_FOUND,
+            AppError::NotAuthorized(_) | AppError::Token(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::QuotaExceeded { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::Database(_)
+            | AppError::Io(_)
+            | AppError::InternalServerError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn code(&self) -> String {
+        match self {
+            AppError::BadRequest(_) => "bad_request".into(),
+            AppError::MissingParameter(_) => "missing_parameter".into(),
+            AppError::InvalidField { .. } => "invalid_field".into(),
+            AppError::Conflict { entity, .. } => format!("{}_already_exists", entity),
+            AppError::NotFound { entity, .. } => format!("{}_not_found", entity),
+            AppError::NotAuthorized(_) => "not_authorized".into(),
+            AppError::Forbidden(_) => "forbidden".into(),
+            AppError::QuotaExceeded { .. } => "quota_exceeded".into(),
+            AppError::Token(_) => "invalid_token".into(),
+            AppError::Database(_) => "database_error".into(),
+            AppError::Unavailable(_) => "service_unavailable".into(),
+            AppError::Io(_) => "io_error".into(),
+            AppError::InternalServerError(_) => "internal_server_error".into(),
+        }
+    }
+
+    pub fn details(&self) -> Option<Value> {
+        match self {
+            AppError::MissingParameter(name) => Some(json!({"parameter": name})),
+            AppError::InvalidField { field, reason } => Some(json!({"field": field, "reason": reason})),
+            AppError::Conflict { entity, id } | AppError::NotFound { entity, id } => {
+                Some(json!({"entity": entity, "id": id}))
+            }
+            AppError::QuotaExceeded { used, quota, requested } => {
+                Some(json!({"used_bytes": used, "quota_bytes": quota, "requested_bytes": requested}))
+            }
+            _ => None,
+        }
+    }
+
+    fn is_internal(&self) -> bool {
+        self.status().is_server_error()
+    }
 }
 
 impl Display for AppError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            AppError::Conflict => write!(f, "conflict"),
-            AppError::NotFound => write!(f, "not found"),
-            AppError::BadRequest => write!(f, "bad request"),
-            AppError::NotAuthorized => write!(f, "not authorized"),
-            AppError::InternalServerError => write!(f, "internal server error")
+            AppError::BadRequest(reason) => write!(f, "bad request: {}", reason),
+            AppError::MissingParameter(name) => write!(f, "missing parameter `{}`", name),
+            AppError::InvalidField { field, reason } => write!(f, "invalid field `{}`: {}", field, reason),
+            AppError::Conflict { entity, id } => write!(f, "{} `{}` already exists", entity, id),
+            AppError::NotFound { entity, id } => write!(f, "{} `{}` not found", entity, id),
+            AppError::NotAuthorized(reason) => write!(f, "not authorized: {}", reason),
+            AppError::Forbidden(reason) => write!(f, "forbidden: {}", reason),
+            AppError::QuotaExceeded { used, quota, requested } => {
+                write!(f, "storage quota exceeded: {} of {} bytes used, {} more requested", used, quota, requested)
+            }
+            AppError::Token(reason) => write!(f, "invalid token: {}", reason),
+            AppError::Database(reason) => write!(f, "database error: {}", reason),
+            AppError::Unavailable(reason) => write!(f, "service unavailable: {}", reason),
+            AppError::Io(reason) => write!(f, "io error: {}", reason),
+            AppError::InternalServerError(reason) => write!(f, "internal server error: {}", reason),
         }
     }
 }
 
 impl std::error::Error for AppError {}
 
+impl From<mongodb::error::Error> for AppError {
+    fn from(value: mongodb::error::Error) -> Self {
+        if let mongodb::error::ErrorKind::Write(WriteFailure::WriteError(e)) = value.kind.as_ref() {
+            if e.code == DUPLICATE_KEY {
+                // "E11000 duplicate key error collection: ... index: <name> dup key: ..."
+                let index = e.message.split("index: ").nth(1)
+                    .and_then(|v| v.split_whitespace().next())
+                    .unwrap_or_default();
+                return AppError::conflict("document", index);
+            }
+        }
+        if is_transient(&value) {
+            AppError::Unavailable(value.to_string())
+        } else {
+            AppError::Database(value.to_string())
+        }
+    }
+}
+
+impl From<bson::ser::Error> for AppError {
+    fn from(value: bson::ser::Error) -> Self {
+        AppError::InternalServerError(value.to_string())
+    }
+}
+
+impl From<bson::de::Error> for AppError {
+    fn from(value: bson::de::Error) -> Self {
+        AppError::Database(value.to_string())
+    }
+}
+
+impl From<std::io::Error> for AppError {
+    fn from(value: std::io::Error) -> Self {
+        AppError::Io(value.to_string())
+    }
+}
+
+impl From<jsonwebtoken::errors::Error> for AppError {
+    fn from(value: jsonwebtoken::errors::Error) -> Self {
+        match value.kind() {
+            JwtErrorKind::ExpiredSignature => AppError::Token("token expired".into()),
+            JwtErrorKind::InvalidToken
+            | JwtErrorKind::InvalidSignature
+            | JwtErrorKind::InvalidAlgorithm
+            | JwtErrorKind::Base64(_)
+            | JwtErrorKind::Json(_)
+            | JwtErrorKind::Utf8(_) => AppError::Token(value.to_string()),
+            _ => AppError::InternalServerError(value.to_string()),
+        }
+    }
+}
+
+impl From<MultipartError> for AppError {
+    fn from(value: MultipartError) -> Self {
+        AppError::BadRequest(value.body_text())
+    }
+}
+
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        match self {
-            AppError::BadRequest => {StatusCode::BAD_REQUEST.into_response()}
-            AppError::Conflict => {StatusCode::CONFLICT.into_response()}
-            AppError::InternalServerError => {StatusCode::INTERNAL_SERVER_ERROR.into_response()}
-            AppError::NotFound => {StatusCode::NOT_FOUND.into_response()}
-            AppError::NotAuthorized => {StatusCode::UNAUTHORIZED.into_response()}
+        let status = self.status();
+        // Подробности внутренних ошибок пишем в лог, а клиенту не отдаём
+        let message = if self.is_internal() {
+            tracing::error!(code = %self.code(), "{}", self);
+            status.canonical_reason().unwrap_or("internal server error").to_lowercase()
+        } else {
+            self.to_string()
+        };
+
+        let body = ErrorBody {
+            code: self.code(),
+            message,
+            details: self.details(),
+            request_id: current_request_id(),
+        };
+
+        let mut response = (status, Json(body)).into_response();
+        if status == StatusCode::SERVICE_UNAVAILABLE {
+            response.headers_mut().insert(RETRY_AFTER, HeaderValue::from(RETRY_AFTER_SECONDS));
         }
+        response
     }
-}
\ No newline at end of file
+}
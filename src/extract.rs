@@ -0,0 +1,329 @@
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use bson::doc;
+use chrono::Utc;
+use mongodb::Database;
+use tokio::fs;
+use tokio::io::AsyncReadExt;
+use tokio::process::Command;
+use crate::config::TextConfig;
+use crate::db::with_retry;
+use crate::error::AppError;
+use crate::structures::FileText;
+
+// Разделитель страниц в выводе pdftotext
+const PAGE_BREAK: char = '\u{c}';
+// Наибольший размер разбираемого XML или HTML. Разметка занимает больше места,
+// чем сам текст, поэтому лимит выше text.max_bytes
+const MAX_DOCUMENT_BYTES: u64 = 64 * 1024 * 1024;
+
+enum Kind {
+    Pdf,
+    Epub,
+    Docx,
+    Odt,
+    Html,
+    Text,
+}
+
+fn kind(filename: &str) -> Option<Kind> {
+    let extension = Path::new(filename).extension()?.to_str()?.to_lowercase();
+    match extension.as_str() {
+        "pdf" => Some(Kind::Pdf),
+        "epub" => Some(Kind::Epub),
+        "docx" => Some(Kind::Docx),
+        "odt" => Some(Kind::Odt),
+        "html" | "htm" | "xhtml" => Some(Kind::Html),
+        "txt" | "md" | "text" => Some(Kind::Text),
+        _ => None,
+    }
+}
+
+// Содержимое записи архива. Размеру из заголовка архива не доверяем: читаем не
+// больше max_bytes, а записи крупнее пропускаем
+pub fn zip_entry(archive: &mut zip::ZipArchive<std::fs::File>, name: &str, max_bytes: u64) -> Option<Vec<u8>> {
+    let entry = archive.by_name(name).ok()?;
+    let mut data = vec![];
+    entry.take(max_bytes + 1).read_to_end(&mut data).ok()?;
+    if data.len() as u64 > max_bytes {
+        return None;
+    }
+    Some(data)
+}
+
+// Путь к OPF-пакету EPUB и его содержимое
+pub fn epub_package(archive: &mut zip::ZipArchive<std::fs::File>, max_bytes: u64) -> Option<(String, String)> {
+    let container = String::from_utf8(zip_entry(archive, "META-INF/container.xml", max_bytes)?).ok()?;
+    let container = roxmltree::Document::parse(&container).ok()?;
+    let path = container.descendants()
+        .find(|v| v.has_tag_name("rootfile"))?
+        .attribute("full-path")?
+        .to_string();
+    let package = String::from_utf8(zip_entry(archive, &path, max_bytes)?).ok()?;
+    Some((path, package))
+}
+
+// Ссылки в OPF заданы относительно каталога самого пакета
+pub fn epub_href(package_path: &str, href: &str) -> String {
+    match Path::new(package_path).parent().map(|v| v.to_string_lossy()) {
+        Some(base) if !base.is_empty() => format!("{}/{}", base, href),
+        _ => href.to_string(),
+    }
+}
+
+fn decode_entity(entity: &str) -> Option<char> {
+    match entity {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some(' '),
+        _ => {
+            let code = entity.strip_prefix("#x").or_else(|| entity.strip_prefix("#X"))
+                .map(|v| u32::from_str_radix(v, 16))
+                .or_else(|| entity.strip_prefix('#').map(|v| v.parse()))?
+                .ok()?;
+            char::from_u32(code)
+        }
+    }
+}
+
+// Грубое, но терпимое к ошибкам разметки удаление тегов: содержимое script/style
+// пропускается, блочные теги заменяются переводом строки
+pub fn strip_html(html: &str) -> String {
+    let mut text = String::with_capacity(html.len() / 2);
+    let mut rest = html;
+
+    while let Some(start) = rest.find(['<', '&']) {
+        text.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if rest.starts_with('&') {
+            match rest.find(';').filter(|v| *v <= 10).and_then(|end| Some((end, decode_entity(&rest[1..end])?))) {
+                Some((end, c)) => {
+                    text.push(c);
+                    rest = &rest[end + 1..];
+                }
+                None => {
+                    text.push('&');
+                    rest = &rest[1..];
+                }
+            }
+            continue;
+        }
+
+        let Some(end) = rest.find('>') else {
+            break;
+        };
+        let closing = rest[1..].starts_with('/');
+        let tag = rest[1..end].trim_start_matches('/').to_lowercase();
+        let name = tag.split(|c: char| c.is_whitespace() || c == '/').next().unwrap_or_default().to_string();
+        rest = &rest[end + 1..];
+
+        if !closing && (name == "script" || name == "style") {
+            let close = format!("</{}", name);
+            rest = match find_ignore_case(rest, &close) {
+                Some(v) => &rest[v..],
+                None => "",
+            };
+            continue;
+        }
+        if matches!(name.as_str(), "p" | "div" | "br" | "li" | "tr" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6") {
+            text.push('\n');
+        }
+    }
+    text.push_str(rest);
+    text
+}
+
+// Поиск ASCII-подстроки, начинающейся с '<', без учёта регистра и без копирования текста
+fn find_ignore_case(haystack: &str, needle: &str) -> Option<usize> {
+    haystack.match_indices('<')
+        .map(|(i, _)| i)
+        .find(|i| haystack.as_bytes()[*i..].get(..needle.len()).is_some_and(|v| v.eq_ignore_ascii_case(needle.as_bytes())))
+}
+
+// Текст абзацев XML-документа: w:p в DOCX, text:p и text:h в ODT
+fn xml_paragraphs(xml: &str, paragraphs: &[&str]) -> Option<String> {
+    let document = roxmltree::Document::parse(xml).ok()?;
+    let text: Vec<String> = document.descendants()
+        .filter(|v| paragraphs.iter().any(|p| v.has_tag_name(*p)))
+        .map(|v| v.descendants().filter_map(|v| v.text().filter(|_| v.is_text())).collect())
+        .collect();
+    Some(text.join("\n"))
+}
+
+fn epub_text(source: &Path) -> Option<Vec<String>> {
+    let mut archive = zip::ZipArchive::new(std::fs::File::open(source).ok()?).ok()?;
+    let (path, package) = epub_package(&mut archive, MAX_DOCUMENT_BYTES)?;
+    let package = roxmltree::Document::parse(&package).ok()?;
+
+    // Главы в порядке spine
+    let items: Vec<_> = package.descendants().filter(|v| v.has_tag_name("item")).collect();
+    let chapters: Vec<String> = package.descendants()
+        .filter(|v| v.has_tag_name("itemref"))
+        .filter_map(|v| v.attribute("idref"))
+        .filter_map(|id| items.iter().find(|v| v.attribute("id") == Some(id)))
+        .filter_map(|v| v.attribute("href"))
+        .map(|href| epub_href(&path, href))
+        .collect();
+
+    Some(chapters.iter()
+        .filter_map(|v| zip_entry(&mut archive, v, MAX_DOCUMENT_BYTES))
+        .map(|v| strip_html(&String::from_utf8_lossy(&v)))
+        .collect())
+}
+
+fn zip_document(source: &Path, entry: &str, paragraphs: &[&str]) -> Option<String> {
+    let mut archive = zip::ZipArchive::new(std::fs::File::open(source).ok()?).ok()?;
+    let xml = String::from_utf8(zip_entry(&mut archive, entry, MAX_DOCUMENT_BYTES)?).ok()?;
+    xml_paragraphs(&xml, paragraphs)
+}
+
+async fn pdf_text(config: &TextConfig, source: &Path) -> Result<Vec<String>, AppError> {
+    let output = Command::new(&config.pdftotext)
+        .args(["-enc", "UTF-8"])
+        .arg(source)
+        .arg("-")
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err(AppError::InternalServerError(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+    }
+    let text = String::from_utf8_lossy(&output.stdout);
+    let mut pages: Vec<String> = text.split(PAGE_BREAK).map(str::to_string).collect();
+    // pdftotext завершает последнюю страницу разделителем
+    if pages.last().is_some_and(|v| v.trim().is_empty()) {
+        pages.pop();
+    }
+    Ok(pages)
+}
+
+// Начало файла не длиннее max_bytes
+async fn read_prefix(path: &Path, max_bytes: u64) -> Result<Vec<u8>, AppError> {
+    let mut data = vec![];
+    fs::File::open(path).await?.take(max_bytes).read_to_end(&mut data).await?;
+    Ok(data)
+}
+
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> Option<T> + Send + 'static) -> Result<T, AppError> {
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| AppError::InternalServerError(e.to_string()))?
+        .ok_or(AppError::BadRequest("unreadable document".into()))
+}
+
+// Обрезаем текст, чтобы документ поместился в лимит MongoDB
+fn truncate(mut pages: Vec<String>, max_bytes: usize) -> Vec<String> {
+    let mut total = 0;
+    for (i, page) in pages.iter_mut().enumerate() {
+        if total + page.len() > max_bytes {
+            let mut end = max_bytes - total;
+            while !page.is_char_boundary(end) {
+                end -= 1;
+            }
+            page.truncate(end);
+            pages.truncate(i + 1);
+            break;
+        }
+        total += page.len();
+    }
+    pages
+}
+
+// Извлекает текст файла; для PDF каждая страница отдельно, для EPUB — главы,
+// для остальных форматов один элемент. None, если формат не поддерживается
+#[tracing::instrument(name = "text.extract", skip(config, post_dir))]
+pub async fn extract_text(config: &TextConfig, post_dir: &Path, filename: &str) -> Result<Option<(Vec<String>, bool)>, AppError> {
+    let Some(kind) = kind(filename) else {
+        return Ok(None);
+    };
+    let source = post_dir.join(filename);
+
+    let (pages, paged) = match kind {
+        Kind::Pdf => (pdf_text(config, &source).await?, true),
+        Kind::Epub => (blocking(move || epub_text(&source)).await?, false),
+        Kind::Docx => (vec![blocking(move || zip_document(&source, "word/document.xml", &["p"])).await?], false),
+        Kind::Odt => (vec![blocking(move || zip_document(&source, "content.xml", &["p", "h"])).await?], false),
+        Kind::Html => (vec![strip_html(&String::from_utf8_lossy(&read_prefix(&source, MAX_DOCUMENT_BYTES).await?))], false),
+        Kind::Text => (vec![String::from_utf8_lossy(&read_prefix(&source, config.max_bytes as u64).await?).into_owned()], false),
+    };
+
+    Ok(Some((truncate(pages, config.max_bytes), paged)))
+}
+
+pub fn text_id(post_id: i64, filename: &str) -> String {
+    format!("{}/{}", post_id, filename)
+}
+
+// Извлекает текст загруженных файлов в фоне и сохраняет его в коллекцию file_texts
+pub async fn extract_texts(config: TextConfig, db: Database, post_id: i64, post_dir: PathBuf, filenames: Vec<String>) {
+    let texts = db.collection::<FileText>("file_texts");
+    let posts = db.collection::<bson::Document>("posts");
+
+    for filename in filenames {
+        let (pages, paged) = match extract_text(&config, &post_dir, &filename).await {
+            Ok(Some(text)) => text,
+            Ok(None) => continue,
+            Err(e) => {
+                tracing::warn!(post = post_id, file = %filename, error = %e, "failed to extract text");
+                continue;
+            }
+        };
+
+        let text = FileText {
+            id: text_id(post_id, &filename),
+            post: post_id,
+            filename: filename.clone(),
+            paged,
+            pages,
+            extracted_at: Utc::now(),
+        };
+        let stored = async {
+            with_retry(|| texts.replace_one(doc! {"_id": &text.id}, &text).upsert(true)).await?;
+            with_retry(|| posts.update_one(
+                doc! {"_id": post_id, "files.filename": &filename},
+                doc! {"$set": {"files.$.text_extracted": true}},
+            )).await?;
+            Ok::<_, AppError>(())
+        };
+        if let Err(e) = stored.await {
+            tracing::warn!(post = post_id, file = %filename, error = %e, "failed to store extracted text");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_script_and_style() {
+        let html = "<html><head><style>p { color: red }</style><script>alert('<p>')</script></head>\
+            <body><p>Hello, <b>world</b></p><SCRIPT type=\"text/javascript\">var a = 1 < 2;</Script>after</body></html>";
+        assert_eq!(strip_html(html), "\nHello, world\nafter");
+        // Незакрытый script скрывает всё до конца документа
+        assert_eq!(strip_html("before<script>if (a < b) {}"), "before");
+        // Теги с похожими именами не считаются script
+        assert_eq!(strip_html("<scripts>kept</scripts>"), "kept");
+    }
+
+    #[test]
+    fn decodes_entities() {
+        assert_eq!(strip_html("Tom &amp; Jerry&nbsp;&#x41;&#66; &unknown; &"), "Tom & Jerry AB &unknown; &");
+    }
+
+    #[test]
+    fn long_document_with_many_scripts() {
+        let html = "<script>x</script>text ".repeat(20_000);
+        assert_eq!(strip_html(&html), "text ".repeat(20_000));
+    }
+
+    #[test]
+    fn find_ignores_case() {
+        assert_eq!(find_ignore_case("a < b </SCRIPT>", "</script"), Some(6));
+        assert_eq!(find_ignore_case("текст </Style>", "</style"), Some(11));
+        assert_eq!(find_ignore_case("</scrip", "</script"), None);
+    }
+}
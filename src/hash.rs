@@ -2,35 +2,30 @@ use std::str::FromStr;
 use argon2_kdf::{Hash, Hasher};
 use chrono::Utc;
 use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use crate::config::JwtConfig;
 use crate::error::AppError;
 use crate::structures::Claims;
 
-pub fn generate_token(id: String) -> Result<String, AppError> {
-    let info = Claims {sub: id, exp: Utc::now().timestamp() + 60 * 60 * 24 * 7};
-    match encode(
+pub fn generate_token(id: String, config: &JwtConfig) -> Result<String, AppError> {
+    let info = Claims {sub: id, exp: Utc::now().timestamp() + config.token_lifetime_secs};
+    Ok(encode(
         &Header::default(),
         &info,
-        &EncodingKey::from_secret(std::env::var("SECRET").map_err(|e| AppError::InternalServerError)?.as_bytes())
-    ) {
-        Ok(token) => Ok(token),
-        Err(_) => Err(AppError::InternalServerError)
-    }
+        &EncodingKey::from_secret(config.secret.as_bytes())
+    )?)
 }
 
-pub fn validate_token(token: String) -> Result<Claims, AppError> {
-    match decode(&token, &DecodingKey::from_secret(std::env::var("SECRET").map_err(|_| AppError::InternalServerError)?.as_bytes()), &Validation::default()) {
-        Ok(data) => Ok(data.claims),
-        Err(_) => Err(AppError::InternalServerError)
-    }
+pub fn validate_token(token: String, config: &JwtConfig) -> Result<Claims, AppError> {
+    Ok(decode(&token, &DecodingKey::from_secret(config.secret.as_bytes()), &Validation::default())?.claims)
 }
 
 pub fn hash_password(password: String) -> Result<String, AppError> {
     match Hasher::default().hash(password.as_bytes()) {
         Ok(hash) => Ok(hash.to_string()),
-        Err(_) => Err(AppError::InternalServerError)
+        Err(e) => Err(AppError::InternalServerError(format!("password hashing failed: {:?}", e)))
     }
 }
 
 pub fn verify_password(password: String, hash: String) -> bool {
     Hash::from_str(hash.as_str()).unwrap().verify(password.as_bytes())
-}
\ No newline at end of file
+}
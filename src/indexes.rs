@@ -0,0 +1,165 @@
+use std::fmt::{Display, Formatter};
+use bson::{doc, Document};
+use futures_util::TryStreamExt;
+use mongodb::error::ErrorKind;
+use mongodb::options::IndexOptions;
+use mongodb::{Database, IndexModel};
+use serde::Serialize;
+use crate::error::AppError;
+
+// Код ошибки NamespaceNotFound: коллекция ещё не создана
+const NAMESPACE_NOT_FOUND: i32 = 26;
+// Сколько повторяющихся значений показывать в сообщении об ошибке
+const DUPLICATES_SHOWN: i64 = 10;
+
+pub struct IndexSpec {
+    pub collection: &'static str,
+    pub name: &'static str,
+    pub keys: Document,
+    pub unique: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum IndexStatus {
+    Present,
+    Created,
+    Missing,
+    Mismatched,
+    Extra,
+    Dropped,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IndexReport {
+    pub collection: String,
+    pub name: String,
+    pub status: IndexStatus,
+}
+
+impl Display for IndexReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}: {:?}", self.collection, self.name, self.status)
+    }
+}
+
+pub fn index_specs() -> Vec<IndexSpec> {
+    vec![
+        IndexSpec { collection: "posts", name: "upload_time", keys: doc! {"upload_time": -1, "_id": -1}, unique: false },
+        IndexSpec { collection: "posts", name: "rating", keys: doc! {"rating": -1}, unique: false },
+        IndexSpec { collection: "posts", name: "keywords", keys: doc! {"keywords": 1}, unique: false },
+        IndexSpec { collection: "posts", name: "author", keys: doc! {"author": 1, "upload_time": -1}, unique: false },
+        IndexSpec { collection: "posts", name: "isbn", keys: doc! {"bibliography.isbn": 1}, unique: false },
+        IndexSpec { collection: "posts", name: "doi", keys: doc! {"bibliography.doi": 1}, unique: false },
+        IndexSpec { collection: "posts", name: "scheduled", keys: doc! {"status": 1, "publish_at": 1}, unique: false },
+        IndexSpec { collection: "users", name: "username", keys: doc! {"username": 1}, unique: true },
+        IndexSpec { collection: "tag_aliases", name: "tag", keys: doc! {"tag": 1}, unique: false },
+        IndexSpec { collection: "file_texts", name: "text", keys: doc! {"pages": "text"}, unique: false },
+        IndexSpec { collection: "file_texts", name: "post", keys: doc! {"post": 1}, unique: false },
+    ]
+}
+
+async fn existing_indexes(db: &Database, collection: &str) -> Result<Vec<IndexModel>, AppError> {
+    match db.collection::<Document>(collection).list_indexes().await {
+        Ok(cursor) => Ok(cursor.try_collect().await?),
+        Err(e) => match e.kind.as_ref() {
+            ErrorKind::Command(c) if c.code == NAMESPACE_NOT_FOUND => Ok(vec![]),
+            _ => Err(e.into()),
+        },
+    }
+}
+
+fn index_name(model: &IndexModel) -> String {
+    model.options.as_ref().and_then(|v| v.name.clone()).unwrap_or_default()
+}
+
+// Текстовый индекс сервер хранит как {_fts: "text", _ftsx: 1}, поля при этом попадают в weights
+fn keys_match(spec: &Document, existing: &Document) -> bool {
+    if spec.values().any(|v| v.as_str() == Some("text")) {
+        return existing.get_str("_fts") == Ok("text");
+    }
+    spec == existing
+}
+
+// Уникальный индекс не создаётся, пока в коллекции есть повторы, а ошибка драйвера
+// не говорит, какие именно. Находим их заранее, чтобы их можно было исправить
+async fn ensure_no_duplicates(db: &Database, spec: &IndexSpec) -> Result<(), AppError> {
+    let key: Document = spec.keys.keys().map(|k| (k.clone(), format!("${}", k).into())).collect();
+    let pipeline = vec![
+        doc! {"$group": {"_id": key, "ids": {"$push": "$_id"}, "count": {"$sum": 1}}},
+        doc! {"$match": {"count": {"$gt": 1}}},
+        doc! {"$limit": DUPLICATES_SHOWN},
+    ];
+    let duplicates: Vec<Document> = db.collection::<Document>(spec.collection).aggregate(pipeline).await?
+        .try_collect().await?;
+    if duplicates.is_empty() {
+        return Ok(());
+    }
+    let described: Vec<String> = duplicates.iter()
+        .map(|v| {
+            let value = v.get_document("_id").map(|v| v.to_string()).unwrap_or_default();
+            let ids = v.get_array("ids").map(|v| v.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(", ")).unwrap_or_default();
+            format!("{} in {}", value, ids)
+        })
+        .collect();
+    Err(AppError::Database(format!(
+        "cannot create unique index {}.{}: duplicate values {}; rename or remove the duplicates and start again \
+         (or set database.sync_indexes = false to start without the index)",
+        spec.collection, spec.name, described.join("; "),
+    )))
+}
+
+// Сверяем индексы с описанием: создаём недостающие и сообщаем о лишних
+pub async fn sync_indexes(db: &Database, dry_run: bool, drop_extra: bool) -> Result<Vec<IndexReport>, AppError> {
+    let specs = index_specs();
+    let mut collections: Vec<&str> = specs.iter().map(|v| v.collection).collect();
+    collections.dedup();
+
+    let mut reports = vec![];
+    for collection in collections {
+        let existing = existing_indexes(db, collection).await?;
+        let wanted: Vec<&IndexSpec> = specs.iter().filter(|v| v.collection == collection).collect();
+        let report = |name: &str, status| IndexReport { collection: collection.into(), name: name.into(), status };
+
+        for spec in wanted.iter() {
+            let status = match existing.iter().find(|v| index_name(v) == spec.name) {
+                Some(model) => {
+                    let unique = model.options.as_ref().and_then(|v| v.unique).unwrap_or(false);
+                    if keys_match(&spec.keys, &model.keys) && unique == spec.unique {
+                        IndexStatus::Present
+                    } else {
+                        IndexStatus::Mismatched
+                    }
+                }
+                None if dry_run => IndexStatus::Missing,
+                None => {
+                    if spec.unique {
+                        ensure_no_duplicates(db, spec).await?;
+                    }
+                    let options = IndexOptions::builder()
+                        .name(spec.name.to_string())
+                        .unique(spec.unique)
+                        .build();
+                    let model = IndexModel::builder().keys(spec.keys.clone()).options(options).build();
+                    db.collection::<Document>(collection).create_index(model).await?;
+                    IndexStatus::Created
+                }
+            };
+            reports.push(report(spec.name, status));
+        }
+
+        for model in existing.iter() {
+            let name = index_name(model);
+            if name == "_id_" || wanted.iter().any(|v| v.name == name) {
+                continue;
+            }
+            if drop_extra && !dry_run {
+                db.collection::<Document>(collection).drop_index(name.clone()).await?;
+                reports.push(report(&name, IndexStatus::Dropped));
+            } else {
+                reports.push(report(&name, IndexStatus::Extra));
+            }
+        }
+    }
+
+    Ok(reports)
+}
@@ -1,33 +1,69 @@
-use axum::extract::Request;
+use std::sync::Arc;
+use axum::extract::{Request, State};
 use axum::http::header::{AUTHORIZATION};
-use axum::http::HeaderMap;
+use axum::http::{HeaderMap, HeaderValue};
 use axum::middleware::Next;
 use axum::response::Response;
 use chrono::Utc;
+use crate::AppState;
+use crate::db::find_record;
 use crate::error::AppError;
 use crate::hash::validate_token;
+use crate::structures::{Claims, User};
 
 pub async fn auth(
+    State(state): State<Arc<AppState>>,
     headers: HeaderMap,
     mut request: Request,
     next: Next
 ) -> Result<Response, AppError> {
     let header = headers.get(AUTHORIZATION)
-        .ok_or(AppError::BadRequest)?
+        .ok_or(AppError::MissingParameter(AUTHORIZATION.to_string()))?;
+    let claims = claims(&state, header).await?;
+
+    tracing::Span::current().record("user_id", claims.sub.as_str());
+    request.extensions_mut().insert(claims);
+
+    Ok(next.run(request).await)
+}
+
+// Для маршрутов, открытых анонимно: токен не обязателен, но если он есть, должен быть верным
+pub async fn optional_auth(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    mut request: Request,
+    next: Next
+) -> Result<Response, AppError> {
+    if let Some(header) = headers.get(AUTHORIZATION) {
+        let claims = claims(&state, header).await?;
+        tracing::Span::current().record("user_id", claims.sub.as_str());
+        request.extensions_mut().insert(claims);
+    }
+
+    Ok(next.run(request).await)
+}
+
+// Токен проверяется вместе с учётной записью: отключённый пользователь теряет
+// доступ сразу, а не по истечении токена
+async fn claims(state: &AppState, header: &HeaderValue) -> Result<Claims, AppError> {
+    let header = header
         .to_str()
-        .map_err(|_| AppError::BadRequest)?;
+        .map_err(|_| AppError::BadRequest("malformed authorization header".into()))?;
 
     if !header.starts_with("Bearer ") {
-        return Err(AppError::BadRequest);
+        return Err(AppError::BadRequest("expected a bearer token".into()));
     }
 
-    let claims = validate_token(header[7..].to_string())?;
+    let claims = validate_token(header[7..].to_string(), &state.config.jwt)?;
 
     if claims.exp < Utc::now().timestamp() {
-        return Err(AppError::NotAuthorized)
+        return Err(AppError::Token("token expired".into()))
     }
 
-    request.extensions_mut().insert(claims);
-
-    Ok(next.run(request).await)
+    let user: Option<User> = find_record(&claims.sub, &state.db().collection("users")).await?;
+    match user {
+        None => Err(AppError::Token("user no longer exists".into())),
+        Some(user) if user.disabled => Err(AppError::Forbidden("account disabled".into())),
+        Some(_) => Ok(claims),
+    }
 }
@@ -0,0 +1,133 @@
+use std::path::{Component, Path};
+use std::sync::Arc;
+use axum::extract::{Request, State};
+use axum::middleware::Next;
+use axum::response::Response;
+use axum::Extension;
+use percent_encoding::percent_decode_str;
+use crate::AppState;
+use crate::access::ensure_visible;
+use crate::antivirus::ensure_clean;
+use crate::config::AntivirusConfig;
+use crate::db::get_record;
+use crate::error::AppError;
+use crate::previews::PREVIEWS_DIR;
+use crate::structures::{Claims, File, Resource};
+use crate::versions::VERSIONS_DIR;
+
+// Доступ к статической раздаче /files/<post>/...: видимость поста и, при require_clean,
+// статус проверки файла. Прежние версии в этом случае отдаются только через
+// /posts/<post>/files/<filename>?version=N, где их статус проверяется; каталог превью
+// не проверяется: превью заражённых файлов не создаются
+pub async fn guard_files(
+    State(state): State<Arc<AppState>>,
+    claims: Option<Extension<Claims>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let path = request.uri().path().trim_start_matches('/');
+    let (post_id, rest) = path.split_once('/').unwrap_or((path, ""));
+    let post_id = post_id.parse::<i64>().map_err(|_| AppError::not_found("post", post_id))?;
+    let post: Resource = get_record(&post_id, &state.db().collection("posts")).await?;
+    ensure_visible(&state, &post, claims.as_deref()).await?;
+    check_target(&state.config.antivirus, &post.files, rest)?;
+    Ok(next.run(request).await)
+}
+
+// Что запрошено внутри каталога поста
+#[derive(Debug, PartialEq, Eq)]
+enum Target {
+    File(String),
+    Preview,
+    Version,
+}
+
+// Путь разбирается так же, как его разберёт ServeDir: после декодирования и
+// нормализации. Иначе ./name, //name или %2Eversions обходят проверки
+fn target(rest: &str) -> Option<Target> {
+    let decoded = percent_decode_str(rest).decode_utf8().ok()?;
+    let mut parts = Vec::new();
+    for component in Path::new(decoded.as_ref()).components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_str()?),
+            _ => return None,
+        }
+    }
+    match parts.as_slice() {
+        [PREVIEWS_DIR, ..] => Some(Target::Preview),
+        [VERSIONS_DIR, ..] => Some(Target::Version),
+        [name] => Some(Target::File(name.to_string())),
+        _ => None,
+    }
+}
+
+fn check_target(config: &AntivirusConfig, files: &[File], rest: &str) -> Result<(), AppError> {
+    let target = target(rest).ok_or_else(|| AppError::not_found("file", rest))?;
+    if !config.require_clean {
+        return Ok(());
+    }
+    match target {
+        Target::Preview => Ok(()),
+        Target::Version => Err(AppError::not_found("file", rest)),
+        Target::File(filename) => {
+            let file = files.iter().find(|v| v.filename == filename)
+                .ok_or_else(|| AppError::not_found("file", &filename))?;
+            ensure_clean(config, &file.filename, file.scan_status)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use crate::structures::ScanStatus;
+    use super::*;
+
+    fn file(filename: &str, status: ScanStatus) -> File {
+        serde_json::from_value(json!({"filename": filename, "size": 1, "scan_status": status})).unwrap()
+    }
+
+    fn strict() -> AntivirusConfig {
+        AntivirusConfig { require_clean: true, ..Default::default() }
+    }
+
+    #[test]
+    fn normalizes_paths() {
+        assert_eq!(target("report.pdf"), Some(Target::File("report.pdf".into())));
+        assert_eq!(target("my%20report.pdf"), Some(Target::File("my report.pdf".into())));
+        assert_eq!(target(".previews/report.png"), Some(Target::Preview));
+        assert_eq!(target(".versions/report.pdf/1"), Some(Target::Version));
+        assert_eq!(target("%2Eversions/report.pdf/1"), Some(Target::Version));
+        assert_eq!(target("%2eversions%2Freport.pdf%2F1"), Some(Target::Version));
+        // Пустые сегменты, . и .. не допускаются
+        assert_eq!(target("dir/./report.pdf"), None);
+        assert_eq!(target("./report.pdf"), None);
+        assert_eq!(target("/report.pdf"), None);
+        assert_eq!(target("%2E/report.pdf"), None);
+        assert_eq!(target("../13/report.pdf"), None);
+        assert_eq!(target("%2E%2E/13/report.pdf"), None);
+        assert_eq!(target(""), None);
+    }
+
+    #[test]
+    fn require_clean_blocks_bypasses() {
+        let files = [
+            file("clean.pdf", ScanStatus::Clean),
+            file("unscanned.pdf", ScanStatus::Unscanned),
+            file("failed.pdf", ScanStatus::Failed),
+        ];
+        let config = strict();
+        assert!(check_target(&config, &files, "clean.pdf").is_ok());
+        assert!(check_target(&config, &files, ".previews/clean.png").is_ok());
+        for path in ["unscanned.pdf", "failed.pdf", "%66ailed.pdf"] {
+            assert!(matches!(check_target(&config, &files, path), Err(AppError::Forbidden(_))), "{} was served", path);
+        }
+        for path in ["./failed.pdf", "/failed.pdf", "%2E/failed.pdf"] {
+            assert!(matches!(check_target(&config, &files, path), Err(AppError::NotFound { .. })), "{} was served", path);
+        }
+        for path in [".versions/failed.pdf/1", "%2Eversions/failed.pdf/1", "%2eversions%2ffailed.pdf%2f1"] {
+            assert!(check_target(&config, &files, path).is_err(), "{} was served", path);
+        }
+        assert!(check_target(&AntivirusConfig::default(), &files, "failed.pdf").is_ok());
+    }
+}
@@ -0,0 +1,66 @@
+use std::time::Duration;
+use axum::http::header::{
+    AUTHORIZATION, CONTENT_SECURITY_POLICY, CONTENT_TYPE, STRICT_TRANSPORT_SECURITY, X_CONTENT_TYPE_OPTIONS,
+};
+use axum::http::{Extensions, HeaderMap, HeaderValue, Method, StatusCode, Version};
+use tower_http::compression::predicate::{DefaultPredicate, Predicate};
+use tower_http::compression::CompressionLayer;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tower_http::set_header::SetResponseHeaderLayer;
+use tower_http::timeout::TimeoutLayer;
+use crate::config::HttpConfig;
+use crate::layers::request_id::REQUEST_ID_HEADER;
+
+// Пустой список источников не разрешает ни одного, "*" разрешает любой
+pub fn cors(config: &HttpConfig) -> CorsLayer {
+    let origins = if config.cors_origins.iter().any(|v| v == "*") {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(config.cors_origins.iter().filter_map(|v| v.parse().ok()))
+    };
+    let request_id = REQUEST_ID_HEADER.clone();
+
+    CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
+        .allow_headers([AUTHORIZATION, CONTENT_TYPE, request_id.clone()])
+        .expose_headers([request_id])
+        .max_age(Duration::from_secs(60 * 60))
+}
+
+pub fn hsts(config: &HttpConfig) -> SetResponseHeaderLayer<Option<HeaderValue>> {
+    let value = (config.hsts_max_age_secs > 0)
+        .then(|| HeaderValue::try_from(format!("max-age={}; includeSubDomains", config.hsts_max_age_secs)).ok())
+        .flatten();
+    SetResponseHeaderLayer::overriding(STRICT_TRANSPORT_SECURITY, value)
+}
+
+// Обработчик может выставить собственную политику (например, /docs), её не перезаписываем
+pub fn content_security_policy(config: &HttpConfig) -> SetResponseHeaderLayer<Option<HeaderValue>> {
+    let value = HeaderValue::try_from(config.content_security_policy.as_str()).ok();
+    SetResponseHeaderLayer::if_not_present(CONTENT_SECURITY_POLICY, value)
+}
+
+// Браузер не должен угадывать тип загруженных пользователями файлов
+pub fn nosniff() -> SetResponseHeaderLayer<HeaderValue> {
+    SetResponseHeaderLayer::overriding(X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"))
+}
+
+fn is_json(_: StatusCode, _: Version, headers: &HeaderMap, _: &Extensions) -> bool {
+    headers.get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"))
+}
+
+// Сжимаем только JSON: файлы отдаются как есть
+pub fn compression(config: &HttpConfig) -> CompressionLayer<impl Predicate> {
+    CompressionLayer::new()
+        .gzip(config.compression)
+        .br(config.compression)
+        .zstd(config.compression)
+        .compress_when(DefaultPredicate::new().and(is_json))
+}
+
+pub fn timeout(config: &HttpConfig) -> TimeoutLayer {
+    TimeoutLayer::new(Duration::from_secs(config.request_timeout_secs))
+}
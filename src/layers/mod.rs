@@ -1 +1,5 @@
-pub mod auth;
\ No newline at end of file
+pub mod auth;
+pub mod files;
+pub mod http;
+pub mod request_id;
+pub mod trace;
\ No newline at end of file
@@ -0,0 +1,55 @@
+use std::time::Instant;
+use axum::extract::{MatchedPath, Request};
+use axum::http::header::CONTENT_LENGTH;
+use axum::middleware::Next;
+use axum::response::Response;
+use tracing::field::Empty;
+use tracing::{info_span, Instrument};
+use crate::layers::request_id::current_request_id;
+use crate::metrics::METRICS;
+
+// Span запроса: метод, маршрут, статус, время ответа и пользователь (заполняет auth)
+pub async fn trace_request(request: Request, next: Next) -> Response {
+    let route = request.extensions()
+        .get::<MatchedPath>()
+        .map(|v| v.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".into());
+    let method = request.method().to_string();
+    let span = info_span!(
+        "request",
+        method = %method,
+        route = %route,
+        request_id = current_request_id().unwrap_or_default(),
+        user_id = Empty,
+        status = Empty,
+        latency_ms = Empty,
+    );
+
+    let start = Instant::now();
+    let response = next.run(request).instrument(span.clone()).await;
+    let elapsed = start.elapsed().as_secs_f64();
+    let latency = elapsed * 1000.0;
+
+    METRICS.http_requests.with_label_values(&[&method, &route, response.status().as_str()]).inc();
+    METRICS.http_duration.with_label_values(&[&method, &route]).observe(elapsed);
+    // Статика из /files не проходит через обработчики, считаем её по Content-Length
+    if route.starts_with("/files") && response.status().is_success() {
+        let length = response.headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok());
+        METRICS.downloaded_bytes.inc_by(length.unwrap_or(0));
+    }
+
+    span.record("status", response.status().as_u16());
+    span.record("latency_ms", latency);
+    span.in_scope(|| {
+        if response.status().is_server_error() {
+            tracing::error!("request failed");
+        } else {
+            tracing::info!("request completed");
+        }
+    });
+
+    response
+}
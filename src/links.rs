@@ -0,0 +1,212 @@
+use std::net::{IpAddr, SocketAddr};
+use bson::doc;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use axum::http::HeaderMap;
+use mongodb::Collection;
+use sha2::Sha256;
+use crate::AppState;
+use crate::config::Config;
+use crate::error::AppError;
+use crate::structures::DownloadLink;
+
+pub const LINKS_COLLECTION: &str = "download_links";
+
+fn secret(config: &Config) -> &[u8] {
+    if config.links.secret.is_empty() { config.jwt.secret.as_bytes() } else { config.links.secret.as_bytes() }
+}
+
+// Подписываются все параметры, от которых зависит доступ: подделать срок или файл нельзя
+// даже при утечке id ссылки
+fn mac(config: &Config, link: &DownloadLink) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret(config)).expect("HMAC accepts keys of any length");
+    let payload = format!(
+        "{}\n{}\n{}\n{}\n{}",
+        link.id,
+        link.post,
+        link.filename,
+        link.expires_at.timestamp(),
+        link.ip.as_deref().unwrap_or_default(),
+    );
+    mac.update(payload.as_bytes());
+    mac
+}
+
+pub fn sign(config: &Config, link: &DownloadLink) -> String {
+    hex::encode(mac(config, link).finalize().into_bytes())
+}
+
+pub fn link_url(config: &Config, link: &DownloadLink) -> String {
+    let filename = url_encode(&link.filename);
+    format!(
+        "/posts/{}/files/{}?link={}&expires={}&signature={}",
+        link.post, filename, link.id, link.expires_at.timestamp(), sign(config, link),
+    )
+}
+
+fn url_encode(value: &str) -> String {
+    percent_encoding::utf8_percent_encode(value, percent_encoding::NON_ALPHANUMERIC).to_string()
+}
+
+// Адрес клиента; за прокси — первый адрес из X-Forwarded-For, если ему разрешено доверять
+pub fn client_ip(config: &Config, headers: &HeaderMap, peer: SocketAddr) -> IpAddr {
+    if config.links.trust_forwarded_for {
+        let forwarded = headers.get("x-forwarded-for")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.split(',').next())
+            .and_then(|v| v.trim().parse().ok());
+        if let Some(ip) = forwarded {
+            return ip;
+        }
+    }
+    peer.ip()
+}
+
+// Проверяет подписанную ссылку. Возвращает запись ссылки, из которой берётся версия
+// файла; скачивание засчитывает redeem, когда файл уже найден
+pub async fn verify(
+    state: &AppState,
+    post_id: i64,
+    filename: &str,
+    id: &str,
+    expires: Option<i64>,
+    signature: Option<&str>,
+    ip: IpAddr,
+) -> Result<DownloadLink, AppError> {
+    let signature = signature.ok_or(AppError::MissingParameter("signature".into()))?;
+    let expires = expires.ok_or(AppError::MissingParameter("expires".into()))?;
+    check_expiry(expires)?;
+
+    let links: Collection<DownloadLink> = state.db().collection(LINKS_COLLECTION);
+    let link = links.find_one(doc! {"_id": id}).await?.ok_or_else(invalid_link)?;
+    check_signature(&state.config, &link, post_id, filename, expires, signature)?;
+    if link.ip.as_ref().is_some_and(|v| v.parse::<IpAddr>().ok() != Some(ip)) {
+        return Err(AppError::Forbidden("download link is bound to another address".into()));
+    }
+    if link.max_downloads.is_some_and(|v| link.downloads >= v) {
+        return Err(AppError::Forbidden("download limit reached".into()));
+    }
+    Ok(link)
+}
+
+fn invalid_link() -> AppError {
+    AppError::Forbidden("invalid or revoked download link".into())
+}
+
+fn check_expiry(expires: i64) -> Result<(), AppError> {
+    let expires_at = DateTime::<Utc>::from_timestamp(expires, 0).ok_or_else(invalid_link)?;
+    if expires_at < Utc::now() {
+        return Err(AppError::Forbidden("download link expired".into()));
+    }
+    Ok(())
+}
+
+// Параметры запроса должны совпасть с записью ссылки, а подпись — с её HMAC
+fn check_signature(
+    config: &Config,
+    link: &DownloadLink,
+    post_id: i64,
+    filename: &str,
+    expires: i64,
+    signature: &str,
+) -> Result<(), AppError> {
+    if link.post != post_id || link.filename != filename || link.expires_at.timestamp() != expires {
+        return Err(invalid_link());
+    }
+    let signature = hex::decode(signature).map_err(|_| invalid_link())?;
+    mac(config, link).verify_slice(&signature).map_err(|_| invalid_link())
+}
+
+// Засчитывает скачивание по ссылке.
+// Счётчик увеличивается только пока лимит не исчерпан, так что параллельные запросы его не превысят
+pub async fn redeem(state: &AppState, link: &DownloadLink) -> Result<(), AppError> {
+    let links: Collection<DownloadLink> = state.db().collection(LINKS_COLLECTION);
+    let counted = links.update_one(
+        doc! {"_id": &link.id, "$or": [
+            {"max_downloads": null},
+            {"$expr": {"$lt": ["$downloads", "$max_downloads"]}},
+        ]},
+        doc! {"$inc": {"downloads": 1_i64}},
+    ).await?;
+    if counted.matched_count == 0 {
+        return Err(AppError::Forbidden("download limit reached".into()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use super::*;
+
+    fn config() -> Config {
+        let mut config = Config::default();
+        config.links.secret = "test secret".into();
+        config
+    }
+
+    fn link() -> DownloadLink {
+        DownloadLink {
+            id: "4f1c2d".into(),
+            post: 12,
+            filename: "report.pdf".into(),
+            version: None,
+            expires_at: DateTime::from_timestamp(Utc::now().timestamp() + 3600, 0).unwrap(),
+            max_downloads: None,
+            downloads: 0,
+            ip: None,
+            created_by: "author@example.com".into(),
+        }
+    }
+
+    fn forbidden(result: Result<(), AppError>) -> bool {
+        matches!(result, Err(AppError::Forbidden(_)))
+    }
+
+    #[test]
+    fn signed_link_verifies() {
+        let (config, link) = (config(), link());
+        let signature = sign(&config, &link);
+        let expires = link.expires_at.timestamp();
+        assert!(check_expiry(expires).is_ok());
+        assert!(check_signature(&config, &link, 12, "report.pdf", expires, &signature).is_ok());
+        assert!(link_url(&config, &link).ends_with(&format!("&signature={}", signature)));
+    }
+
+    #[test]
+    fn tampered_link_is_rejected() {
+        let (config, link) = (config(), link());
+        let signature = sign(&config, &link);
+        let expires = link.expires_at.timestamp();
+
+        let mut tampered = signature.clone().into_bytes();
+        tampered[0] = if tampered[0] == b'0' { b'1' } else { b'0' };
+        let tampered = String::from_utf8(tampered).unwrap();
+        assert!(forbidden(check_signature(&config, &link, 12, "report.pdf", expires, &tampered)));
+        assert!(forbidden(check_signature(&config, &link, 12, "report.pdf", expires, "not hex")));
+
+        // Запрос к другому посту, файлу или с другим сроком
+        assert!(forbidden(check_signature(&config, &link, 13, "report.pdf", expires, &signature)));
+        assert!(forbidden(check_signature(&config, &link, 12, "other.pdf", expires, &signature)));
+        assert!(forbidden(check_signature(&config, &link, 12, "report.pdf", expires + 3600, &signature)));
+
+        // Запись изменена без новой подписи: подпись другой ссылки не подходит
+        let other = DownloadLink { id: "9a8b7c".into(), ..link.clone() };
+        assert!(forbidden(check_signature(&config, &other, 12, "report.pdf", expires, &signature)));
+        let extended = DownloadLink { expires_at: link.expires_at + Duration::days(1), ..link.clone() };
+        let extended_expires = extended.expires_at.timestamp();
+        assert!(forbidden(check_signature(&config, &extended, 12, "report.pdf", extended_expires, &signature)));
+
+        // Подпись другим ключом
+        let mut other_config = config.clone();
+        other_config.links.secret = "another secret".into();
+        assert!(forbidden(check_signature(&other_config, &link, 12, "report.pdf", expires, &signature)));
+    }
+
+    #[test]
+    fn expired_link_is_rejected() {
+        let expired = Utc::now().timestamp() - 1;
+        assert!(forbidden(check_expiry(expired)));
+        assert!(forbidden(check_expiry(i64::MAX)));
+    }
+}
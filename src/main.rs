@@ -1,85 +1,281 @@
 mod structures;
+mod access;
+mod bibliography;
+mod openapi;
+mod previews;
+mod quota;
+mod indexes;
+mod migrations;
+mod cli;
+mod config;
 mod error;
+mod antivirus;
 mod db;
+mod digest;
+mod extract;
 mod endpoints;
 mod hash;
 mod layers;
+mod links;
+mod metrics;
+mod publishing;
+mod routes;
+mod tags;
+mod tls;
+mod versions;
 
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 use axum::{middleware, Router};
-use axum::routing::{get, post, put};
-use mongodb::{Client};
+use axum::extract::DefaultBodyLimit;
+use mongodb::{Client, Database};
 use mongodb::options::{ClientOptions, Credential};
 use dotenvy::dotenv;
 use tokio::fs;
 use structures::IdGenerator;
-use tokio::net::TcpListener;
+use clap::Parser;
+use crate::cli::{Cli, Command, ConfigCommand};
+use crate::config::{Config, DatabaseConfig, LogFormat, LoggingConfig};
+use crate::db::{set_retry_policy, RetryPolicy};
+use crate::indexes::{sync_indexes, IndexStatus};
+use crate::migrations::run_migrations;
+use utoipa::OpenApi;
+use crate::openapi::ApiDoc;
+use axum_server::Handle;
 use tower_http::services::ServeDir;
-use crate::endpoints::files::{download_post_file, list_post_files, upload_files_to_post};
-use crate::endpoints::posts::{create_post, get_posts, rate_post};
-use crate::endpoints::user::{login, register, update_token};
-use crate::layers::auth::auth;
+use crate::layers::auth::{auth, optional_auth};
+use crate::layers::files::guard_files;
+use crate::layers::http;
+use crate::layers::request_id::request_id;
+use crate::layers::trace::trace_request;
+use crate::metrics::mongodb_event_handler;
+use crate::routes::{routes, Group};
+use tracing_subscriber::EnvFilter;
+use crate::tags::Taxonomy;
 
 struct AppState {
+    config: Config,
     client: Client,
     id_gen: IdGenerator,
+    taxonomy: Taxonomy,
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    dotenv()?;
-    let uri = std::env::var("MONGODB_URI")?;
-    let mut client_options = ClientOptions::parse(uri).await?;
+impl AppState {
+    fn db(&self) -> Database {
+        self.client.database(&self.config.database.name)
+    }
+}
+
+async fn connect(config: &DatabaseConfig) -> Result<Client, mongodb::error::Error> {
+    let mut client_options = ClientOptions::parse(&config.uri).await?;
     let credentials = Credential::builder()
-        .username(std::env::var("MONGO_USERNAME")?.to_string())
-        .password(std::env::var("PASSWORD")?.to_string())
-        .source("alexandria".to_string())
+        .username(config.username.clone())
+        .password(config.password.clone())
+        .source(config.name.clone())
         .build();
     client_options.credential = Some(credentials);
+    client_options.command_event_handler = Some(mongodb_event_handler());
+
+    Client::with_options(client_options)
+}
+
+impl AppState {
+    async fn new(config: Config) -> Result<AppState, mongodb::error::Error> {
+        set_retry_policy(RetryPolicy::from(&config.database));
+        let client = connect(&config.database).await?;
+        let db = client.database(&config.database.name);
+
+        Ok(AppState {
+            id_gen: IdGenerator::new(db.clone()).await,
+            taxonomy: Taxonomy::new(&db),
+            client,
+            config,
+        })
+    }
+}
+
+fn init_tracing(config: &LoggingConfig) {
+    let filter = EnvFilter::new(&config.filter);
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
+    match config.format {
+        LogFormat::Pretty => builder.init(),
+        LogFormat::Json => builder.json().flatten_event(true).with_current_span(true).init(),
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    dotenv().ok();
+    let cli = Cli::parse();
 
-    let client = Client::with_options(client_options)?;
+    if let Some(Command::Openapi) = cli.command {
+        println!("{}", ApiDoc::openapi().to_pretty_json()?);
+        return Ok(());
+    }
 
-    let state = Arc::new(AppState { client: client.clone(), id_gen: IdGenerator::new(client.database("alexandria")).await });
+    let config = match Config::load(cli.config.as_deref()) {
+        Ok(config) => config,
+        Err(errors) => {
+            for error in errors.iter() {
+                eprintln!("config error: {}", error);
+            }
+            return Err(format!("invalid configuration ({} errors)", errors.len()).into());
+        }
+    };
 
-    fs::create_dir_all("uploads").await.unwrap();
+    init_tracing(&config.logging);
 
-    let files = Router::new()
-        // Маршрут для загрузки файлов в конкретный пост
-        .route("/posts/:post_id/upload", post(upload_files_to_post))
-        .route_layer(middleware::from_fn(auth))
-        // Маршрут для скачивания файла из конкретного поста
-        .route(
-            "/posts/:post_id/files/:filename",
-            get(download_post_file),
-        )
-        // Маршрут для получения списка файлов поста
-        .route("/posts/:post_id/files", get(list_post_files))
+    let state = match cli.command.unwrap_or(Command::Serve) {
+        Command::Config { command: ConfigCommand::Check } => {
+            println!("configuration is valid");
+            return Ok(());
+        }
+        Command::Serve => Arc::new(AppState::new(config).await?),
+        Command::Openapi => unreachable!(),
+        command => return cli::run(command, Arc::new(AppState::new(config).await?)).await,
+    };
+
+    serve(state).await
+}
+
+async fn serve(state: Arc<AppState>) -> Result<(), Box<dyn std::error::Error>> {
+    fs::create_dir_all(&state.config.storage.uploads).await?;
+
+    if state.config.database.migrate_on_startup {
+        for report in run_migrations(&state.db(), &state.config.storage.uploads, false).await? {
+            tracing::info!(version = report.version, name = report.name, documents = report.documents, "applied migration");
+        }
+    }
+
+    if state.config.database.sync_indexes {
+        let reports = sync_indexes(&state.db(), false, state.config.database.drop_extra_indexes).await?;
+        for report in reports.iter().filter(|v| v.status != IndexStatus::Present) {
+            tracing::info!(collection = report.collection, name = report.name, status = ?report.status, "index");
+        }
+    }
+
+    let (mut upload, mut files, mut with, mut optional, mut without) =
+        (Router::new(), Router::new(), Router::new(), Router::new(), Router::new());
+    for route in routes() {
+        tracing::debug!(method = %route.method, path = route.path, group = ?route.group, "route");
+        let router = match route.group {
+            Group::Upload => &mut upload,
+            Group::Files => &mut files,
+            Group::Authenticated => &mut with,
+            Group::Optional => &mut optional,
+            Group::Public => &mut without,
+        };
+        *router = std::mem::take(router).route(route.path, route.handler);
+    }
+
+    let files = upload
+        .route_layer(middleware::from_fn_with_state(state.clone(), auth))
+        .layer(DefaultBodyLimit::max(state.config.limits.max_upload_bytes))
+        .merge(files)
         // Статический обработчик для прямого доступа к файлам
-        .nest_service("/files", ServeDir::new("uploads"))
+        .nest("/files", Router::new()
+            .fallback_service(ServeDir::new(&state.config.storage.uploads))
+            .layer(middleware::from_fn_with_state(state.clone(), guard_files)))
+        // Файлы закрытых постов доступны только с токеном, остальные — и без него
+        .layer(middleware::from_fn_with_state(state.clone(), optional_auth))
         .with_state(state.clone());
 
-    let with = Router::new()
-        .route("/create_post", post(create_post))
-        .route("/get_posts", get(get_posts))
-        .route("/rate_post", post(rate_post))
-        .route("/update_token", put(update_token))
-        .layer(middleware::from_fn(auth))
+    let with = with
+        .layer(middleware::from_fn_with_state(state.clone(), auth))
+        .layer(http::timeout(&state.config.http))
         .with_state(state.clone());
 
-    let without = Router::new()
-        .route("/register", post(register))
-        .route("/login", post(login))
-        .with_state(state);
+    let without = optional
+        .route_layer(middleware::from_fn_with_state(state.clone(), optional_auth))
+        .merge(without)
+        .layer(http::timeout(&state.config.http))
+        .with_state(state.clone());
 
     let app = Router::new()
         .merge(with)
         .merge(without)
-        .merge(files);
+        .merge(files)
+        .layer(http::compression(&state.config.http))
+        .layer(http::content_security_policy(&state.config.http))
+        .layer(http::hsts(&state.config.http))
+        .layer(http::nosniff())
+        .layer(http::cors(&state.config.http))
+        .layer(middleware::from_fn(trace_request))
+        .layer(middleware::from_fn(request_id));
+
+    let listener = std::net::TcpListener::bind(&state.config.server.listen)?;
+    listener.set_nonblocking(true)?;
+    let address = listener.local_addr()?;
+    let handle = Handle::new();
 
-    let addr = TcpListener::bind(std::env::var("SERVER_URL")?.to_string()).await?;
+    tokio::spawn(publishing::run_scheduler(state.db(), state.config.publishing.clone()));
 
-    axum::serve(addr, app).await?;
+    let mut server = if state.config.tls.enabled {
+        let rustls = tls::load_config(&state.config.tls).await?;
+        tokio::spawn(tls::watch_certificates(rustls.clone(), state.config.tls.clone()));
 
-    println!("Hello, world!");
+        if let Some(redirect) = state.config.tls.redirect_listen.as_ref() {
+            let redirect_listener = std::net::TcpListener::bind(redirect)?;
+            redirect_listener.set_nonblocking(true)?;
+            tracing::info!(address = %redirect, "redirecting HTTP to HTTPS");
+            let redirect = axum_server::from_tcp(redirect_listener)
+                .handle(handle.clone())
+                .serve(tls::redirect_app(address.port()).into_make_service());
+            tokio::spawn(async move {
+                if let Err(e) = redirect.await {
+                    tracing::error!(error = %e, "redirect listener failed");
+                }
+            });
+        }
+
+        tokio::spawn(axum_server::from_tcp_rustls(listener, rustls).handle(handle.clone()).serve(app.into_make_service_with_connect_info::<SocketAddr>()))
+    } else {
+        tokio::spawn(axum_server::from_tcp(listener).handle(handle.clone()).serve(app.into_make_service_with_connect_info::<SocketAddr>()))
+    };
+    tracing::info!(%address, tls = state.config.tls.enabled, "listening");
+
+    tokio::select! {
+        result = &mut server => result??,
+        _ = shutdown_signal() => {
+            // Перестаём принимать соединения и даём текущим запросам завершиться,
+            // по истечении таймаута оставшиеся соединения закрываются
+            let timeout = Duration::from_secs(state.config.server.shutdown_timeout_secs);
+            tracing::info!(timeout_secs = timeout.as_secs(), "shutting down");
+            handle.graceful_shutdown(Some(timeout));
+            server.await??;
+        }
+    }
+
+    tracing::info!("stopped");
     Ok(())
+}
+
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!(error = %e, "failed to listen for SIGINT");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => { signal.recv().await; }
+            Err(e) => {
+                tracing::error!(error = %e, "failed to listen for SIGTERM");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
 }
\ No newline at end of file
@@ -0,0 +1,88 @@
+use std::sync::LazyLock;
+use std::time::Duration;
+use axum::http::header::CONTENT_TYPE;
+use axum::response::IntoResponse;
+use mongodb::event::command::CommandEvent;
+use mongodb::event::EventHandler;
+use prometheus::{
+    register_histogram_vec_with_registry, register_int_counter_vec_with_registry,
+    register_int_counter_with_registry, Encoder, HistogramVec, IntCounter, IntCounterVec, Registry, TextEncoder,
+};
+
+// Границы гистограмм в секундах: от миллисекунды до десяти секунд
+const LATENCY_BUCKETS: &[f64] = &[0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+pub struct Metrics {
+    registry: Registry,
+    pub http_requests: IntCounterVec,
+    pub http_duration: HistogramVec,
+    pub uploaded_files: IntCounter,
+    pub uploaded_bytes: IntCounter,
+    pub downloaded_bytes: IntCounter,
+    pub logins: IntCounterVec,
+    pub ratings: IntCounterVec,
+    pub scans: IntCounterVec,
+    pub mongodb_duration: HistogramVec,
+}
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("alexandria".into()), None).unwrap();
+        Metrics {
+            http_requests: register_int_counter_vec_with_registry!(
+                "http_requests_total", "HTTP requests by route and status", &["method", "route", "status"], registry
+            ).unwrap(),
+            http_duration: register_histogram_vec_with_registry!(
+                "http_request_duration_seconds", "HTTP request latency", &["method", "route"],
+                LATENCY_BUCKETS.to_vec(), registry
+            ).unwrap(),
+            uploaded_files: register_int_counter_with_registry!(
+                "uploaded_files_total", "Files stored through the upload endpoint", registry
+            ).unwrap(),
+            uploaded_bytes: register_int_counter_with_registry!(
+                "uploaded_bytes_total", "Bytes stored through the upload endpoint", registry
+            ).unwrap(),
+            downloaded_bytes: register_int_counter_with_registry!(
+                "downloaded_bytes_total", "Bytes served from post files", registry
+            ).unwrap(),
+            logins: register_int_counter_vec_with_registry!(
+                "logins_total", "Login attempts by result", &["result"], registry
+            ).unwrap(),
+            ratings: register_int_counter_vec_with_registry!(
+                "ratings_total", "Post rating events by rating", &["rating"], registry
+            ).unwrap(),
+            scans: register_int_counter_vec_with_registry!(
+                "antivirus_scans_total", "Antivirus scans by result", &["result"], registry
+            ).unwrap(),
+            mongodb_duration: register_histogram_vec_with_registry!(
+                "mongodb_command_duration_seconds", "MongoDB command latency", &["command", "outcome"],
+                LATENCY_BUCKETS.to_vec(), registry
+            ).unwrap(),
+            registry,
+        }
+    }
+
+    pub fn observe_mongodb(&self, command: &str, outcome: &str, duration: Duration) {
+        self.mongodb_duration.with_label_values(&[command, outcome]).observe(duration.as_secs_f64());
+    }
+}
+
+// Обработчик событий драйвера: время выполнения каждой команды MongoDB
+pub fn mongodb_event_handler() -> EventHandler<CommandEvent> {
+    EventHandler::callback(|event| match event {
+        CommandEvent::Succeeded(e) => METRICS.observe_mongodb(&e.command_name, "success", e.duration),
+        CommandEvent::Failed(e) => METRICS.observe_mongodb(&e.command_name, "failure", e.duration),
+        _ => {}
+    })
+}
+
+pub async fn metrics() -> impl IntoResponse {
+    let encoder = TextEncoder::new();
+    let mut buffer = vec![];
+    if let Err(e) = encoder.encode(&METRICS.registry.gather(), &mut buffer) {
+        tracing::error!(error = %e, "failed to encode metrics");
+    }
+    ([(CONTENT_TYPE, encoder.format_type().to_string())], buffer)
+}
@@ -0,0 +1,281 @@
+use std::path::{Path, PathBuf};
+use bson::{doc, Bson, Document};
+use chrono::{DateTime, Utc};
+use futures_util::future::BoxFuture;
+use futures_util::{FutureExt, TryStreamExt};
+use mongodb::{Collection, Database};
+use serde::{Deserialize, Serialize};
+use crate::digest::digest_file;
+use crate::error::AppError;
+use crate::structures::deserialize_date;
+
+// Миграция возвращает количество изменённых (или, при dry-run, затрагиваемых) документов
+type MigrationFn = fn(MigrationContext, bool) -> BoxFuture<'static, Result<u64, AppError>>;
+
+// Всё, что может понадобиться миграции: база и каталог с загруженными файлами
+#[derive(Clone)]
+pub struct MigrationContext {
+    pub db: Database,
+    pub uploads: PathBuf,
+}
+
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    run: MigrationFn,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppliedMigration {
+    #[serde(rename = "_id")]
+    pub version: i64,
+    pub name: String,
+    #[serde(deserialize_with = "deserialize_date")]
+    pub applied_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MigrationReport {
+    pub version: i64,
+    pub name: String,
+    pub documents: u64,
+    pub applied: bool,
+}
+
+// Новые миграции добавляются только в конец списка
+pub const MIGRATIONS: &[Migration] = &[
+    Migration { version: 1, name: "dates_to_bson_datetime", run: dates_to_bson_datetime },
+    Migration { version: 2, name: "backfill_user_and_post_fields", run: backfill_user_and_post_fields },
+    Migration { version: 3, name: "file_metadata", run: file_metadata },
+    Migration { version: 4, name: "storage_usage", run: storage_usage },
+    Migration { version: 5, name: "deduplicate_files", run: deduplicate_files },
+    Migration { version: 6, name: "post_visibility", run: post_visibility },
+    Migration { version: 7, name: "post_status", run: post_status },
+];
+
+fn migrations_collection(db: &Database) -> Collection<AppliedMigration> {
+    db.collection("migrations")
+}
+
+pub async fn applied_migrations(db: &Database) -> Result<Vec<AppliedMigration>, AppError> {
+    Ok(migrations_collection(db).find(doc! {}).sort(doc! {"_id": 1}).await?.try_collect().await?)
+}
+
+pub async fn run_migrations(db: &Database, uploads: &Path, dry_run: bool) -> Result<Vec<MigrationReport>, AppError> {
+    let applied: Vec<i64> = applied_migrations(db).await?.into_iter().map(|v| v.version).collect();
+    let context = MigrationContext { db: db.clone(), uploads: uploads.to_path_buf() };
+    let mut reports = vec![];
+
+    for migration in MIGRATIONS.iter().filter(|v| !applied.contains(&v.version)) {
+        let documents = (migration.run)(context.clone(), dry_run).await?;
+        if !dry_run {
+            migrations_collection(db).clone_with_type::<Document>().insert_one(doc! {
+                "_id": migration.version,
+                "name": migration.name,
+                "applied_at": bson::DateTime::now(),
+            }).await?;
+        }
+        reports.push(MigrationReport {
+            version: migration.version,
+            name: migration.name.to_string(),
+            documents,
+            applied: !dry_run,
+        });
+    }
+
+    Ok(reports)
+}
+
+async fn convert_dates(coll: Collection<Document>, field: &str, dry_run: bool) -> Result<u64, AppError> {
+    let filter = doc! {field: {"$type": "string"}};
+    if dry_run {
+        return Ok(coll.count_documents(filter).await?);
+    }
+
+    let mut converted = 0;
+    let mut cursor = coll.find(filter).await?;
+    while let Some(document) = cursor.try_next().await? {
+        let (Some(id), Ok(value)) = (document.get("_id"), document.get_str(field)) else {
+            continue;
+        };
+        let date = DateTime::parse_from_rfc3339(value)
+            .map_err(|e| AppError::InternalServerError(format!("{} of {}: {}", field, id, e)))?
+            .with_timezone(&Utc);
+        coll.update_one(
+            doc! {"_id": id},
+            doc! {"$set": {field: bson::DateTime::from_chrono(date)}},
+        ).await?;
+        converted += 1;
+    }
+    Ok(converted)
+}
+
+async fn backfill(coll: Collection<Document>, field: &str, value: Bson, dry_run: bool) -> Result<u64, AppError> {
+    let filter = doc! {field: {"$exists": false}};
+    if dry_run {
+        return Ok(coll.count_documents(filter).await?);
+    }
+    Ok(coll.update_many(filter, doc! {"$set": {field: value}}).await?.modified_count)
+}
+
+fn dates_to_bson_datetime(context: MigrationContext, dry_run: bool) -> BoxFuture<'static, Result<u64, AppError>> {
+    async move {
+        let db = context.db;
+        let users = db.collection::<Document>("users");
+        let posts = db.collection::<Document>("posts");
+
+        Ok(convert_dates(users.clone(), "last_upload", dry_run).await?
+            + convert_dates(users, "register_date", dry_run).await?
+            + convert_dates(posts, "upload_time", dry_run).await?)
+    }.boxed()
+}
+
+fn backfill_user_and_post_fields(context: MigrationContext, dry_run: bool) -> BoxFuture<'static, Result<u64, AppError>> {
+    async move {
+        let db = context.db;
+        let users = db.collection::<Document>("users");
+        let posts = db.collection::<Document>("posts");
+
+        Ok(backfill(users.clone(), "role", "User".into(), dry_run).await?
+            + backfill(users.clone(), "disabled", false.into(), dry_run).await?
+            + backfill(users.clone(), "following", Bson::Array(vec![]), dry_run).await?
+            + backfill(users, "followed_keywords", Bson::Array(vec![]), dry_run).await?
+            + backfill(posts, "hidden", false.into(), dry_run).await?)
+    }.boxed()
+}
+
+// Метаданные файлов, загруженных до их появления: тип и контрольная сумма
+// считаются по файлу на диске, загрузившим считается автор поста
+fn file_metadata(context: MigrationContext, dry_run: bool) -> BoxFuture<'static, Result<u64, AppError>> {
+    async move {
+        let posts = context.db.collection::<Document>("posts");
+        let filter = doc! {"files": {"$elemMatch": {"sha256": {"$exists": false}}}};
+        if dry_run {
+            return Ok(posts.count_documents(filter).await?);
+        }
+
+        let mut updated = 0;
+        let mut cursor = posts.find(filter).await?;
+        while let Some(post) = cursor.try_next().await? {
+            let (Some(id), Ok(files)) = (post.get("_id"), post.get_array("files")) else {
+                continue;
+            };
+            let post_dir = context.uploads.join(id.to_string());
+            let mut files = files.clone();
+
+            for file in files.iter_mut().filter_map(|v| v.as_document_mut()) {
+                if file.contains_key("sha256") {
+                    continue;
+                }
+                let Ok(filename) = file.get_str("filename") else {
+                    continue;
+                };
+                match digest_file(&post_dir.join(filename)).await {
+                    Ok(metadata) => {
+                        file.insert("sha256", metadata.sha256);
+                        file.insert("mime_type", metadata.mime_type);
+                    }
+                    // Файла на диске нет: поля остаются пустыми
+                    Err(_) => {
+                        file.insert("sha256", Bson::Null);
+                        file.insert("mime_type", Bson::Null);
+                    }
+                }
+                file.insert("uploader", post.get("author").cloned().unwrap_or(Bson::Null));
+                file.insert("uploaded_at", post.get("upload_time").cloned().unwrap_or(Bson::Null));
+                file.insert("description", Bson::Null);
+            }
+
+            posts.update_one(doc! {"_id": id}, doc! {"$set": {"files": files}}).await?;
+            updated += 1;
+        }
+        Ok(updated)
+    }.boxed()
+}
+
+// Занятое место пользователей по записям о файлах: каждый файл на счету загрузившего
+fn storage_usage(context: MigrationContext, dry_run: bool) -> BoxFuture<'static, Result<u64, AppError>> {
+    async move {
+        let users = context.db.collection::<Document>("users");
+        let posts = context.db.collection::<Document>("posts");
+        if dry_run {
+            return Ok(users.count_documents(doc! {}).await?);
+        }
+
+        recount_usage(&users, &posts).await
+    }.boxed()
+}
+
+// Пересчёт занятого места с учётом прежних версий файлов
+async fn recount_usage(users: &Collection<Document>, posts: &Collection<Document>) -> Result<u64, AppError> {
+    let usage: Vec<Document> = posts.aggregate(vec![
+        doc! {"$unwind": "$files"},
+        doc! {"$project": {"entries": {"$concatArrays": [
+            [{"uploader": "$files.uploader", "size": "$files.size"}],
+            {"$ifNull": ["$files.versions", []]},
+        ]}}},
+        doc! {"$unwind": "$entries"},
+        doc! {"$group": {"_id": "$entries.uploader", "bytes": {"$sum": "$entries.size"}}},
+    ]).await?.try_collect().await?;
+
+    let updated = users.update_many(doc! {}, doc! {"$set": {"used_bytes": 0_i64}}).await?.matched_count;
+    for entry in usage {
+        let bytes = entry.get("bytes").and_then(|v| v.as_i64().or_else(|| v.as_i32().map(i64::from)));
+        let (Ok(user), Some(bytes)) = (entry.get_str("_id"), bytes) else {
+            continue;
+        };
+        users.update_one(doc! {"_id": user}, doc! {"$set": {"used_bytes": bytes}}).await?;
+    }
+    Ok(updated)
+}
+
+// Раньше повторная загрузка добавляла в пост вторую запись с тем же именем, а файл
+// перезаписывался. Оставляем последнюю запись: она соответствует файлу на диске
+fn deduplicate_files(context: MigrationContext, dry_run: bool) -> BoxFuture<'static, Result<u64, AppError>> {
+    async move {
+        let users = context.db.collection::<Document>("users");
+        let posts = context.db.collection::<Document>("posts");
+
+        let mut updated = 0;
+        let mut cursor = posts.find(doc! {"files.1": {"$exists": true}}).await?;
+        while let Some(post) = cursor.try_next().await? {
+            let (Some(id), Ok(files)) = (post.get("_id"), post.get_array("files")) else {
+                continue;
+            };
+            let mut unique: Vec<Bson> = vec![];
+            for file in files.iter().rev() {
+                let filename = file.as_document().and_then(|v| v.get_str("filename").ok());
+                let seen = unique.iter().any(|v| v.as_document().and_then(|v| v.get_str("filename").ok()) == filename);
+                if !seen {
+                    unique.push(file.clone());
+                }
+            }
+            if unique.len() == files.len() {
+                continue;
+            }
+            unique.reverse();
+            if !dry_run {
+                posts.update_one(doc! {"_id": id}, doc! {"$set": {"files": unique}}).await?;
+            }
+            updated += 1;
+        }
+
+        if !dry_run && updated > 0 {
+            recount_usage(&users, &posts).await?;
+        }
+        Ok(updated)
+    }.boxed()
+}
+
+fn post_visibility(context: MigrationContext, dry_run: bool) -> BoxFuture<'static, Result<u64, AppError>> {
+    async move {
+        backfill(context.db.collection("posts"), "visibility", "Public".into(), dry_run).await
+    }.boxed()
+}
+
+// Посты, созданные до появления черновиков, уже опубликованы
+fn post_status(context: MigrationContext, dry_run: bool) -> BoxFuture<'static, Result<u64, AppError>> {
+    async move {
+        backfill(context.db.collection("posts"), "status", "Published".into(), dry_run).await
+    }.boxed()
+}
@@ -0,0 +1,109 @@
+use axum::http::header::CONTENT_SECURITY_POLICY;
+use axum::http::HeaderName;
+use axum::response::Html;
+use axum::Json;
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+use crate::endpoints::{archive, feed, files, health, links, posts, search, tags, user};
+use crate::error::ErrorBody;
+use crate::structures::{
+    Bibliography, CreateAliases, CreateLink, CreateResource, Feed, FeedCursor, File, FileVersion, Follow, PostStatus, PublishPost,
+    RatedPost, Rating, Resource, Role, ScanStatus, SearchHit, SendResource, SetQuota, SignedLink, SetVisibility, TagAlias, TagCount,
+    TextLocation, Usage, Visibility,
+};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Alexandria", description = "File library server API"),
+    paths(
+        user::register,
+        user::login,
+        user::update_token,
+        user::get_usage,
+        user::set_quota,
+        posts::create_post,
+        posts::get_posts,
+        posts::rate_post,
+        posts::set_visibility,
+        posts::publish_post,
+        feed::follow,
+        feed::unfollow,
+        feed::get_feed,
+        tags::get_tags,
+        tags::autocomplete_tags,
+        tags::list_aliases,
+        tags::create_aliases,
+        tags::delete_alias,
+        files::upload_files_to_post,
+        files::download_post_file,
+        files::list_post_files,
+        files::list_file_versions,
+        files::restore_file_version,
+        links::create_link,
+        links::revoke_link,
+        files::get_preview,
+        archive::download_post_archive,
+        search::search,
+        health::healthz,
+        health::readyz,
+    ),
+    components(schemas(
+        Bibliography, CreateAliases, CreateLink, CreateResource, ErrorBody, Feed, FeedCursor, File, FileVersion, Follow, PostStatus,
+        PublishPost, RatedPost, Rating, Resource, Role, ScanStatus, SearchHit, SendResource, SetQuota, SetVisibility, SignedLink,
+        TagAlias, TagCount, TextLocation, Usage, Visibility,
+        files::UploadResponse, archive::ArchiveFormat, health::Check, health::Readiness,
+    )),
+    modifiers(&BearerAuth),
+)]
+pub struct ApiDoc;
+
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).bearer_format("JWT").build()),
+        );
+    }
+}
+
+const DOCS_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+    <title>Alexandria API</title>
+    <meta charset="utf-8"/>
+    <meta name="viewport" content="width=device-width, initial-scale=1">
+</head>
+<body>
+    <redoc spec-url="/openapi.json"></redoc>
+    <script src="https://cdn.redoc.ly/redoc/latest/bundles/redoc.standalone.js"></script>
+</body>
+</html>
+"#;
+
+pub async fn openapi_json() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+// Redoc загружается с CDN и использует встроенные стили и web worker
+const DOCS_POLICY: &str = "default-src 'none'; script-src https://cdn.redoc.ly; \
+    style-src 'unsafe-inline' https://fonts.googleapis.com; font-src https://fonts.gstatic.com; \
+    img-src 'self' data: https://cdn.redoc.ly; connect-src 'self'; worker-src blob:";
+
+pub async fn docs() -> ([(HeaderName, &'static str); 1], Html<&'static str>) {
+    ([(CONTENT_SECURITY_POLICY, DOCS_POLICY)], Html(DOCS_PAGE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Хеши паролей не должны попадать ни в одну схему
+    #[test]
+    fn no_password_hash() {
+        let spec = ApiDoc::openapi().to_json().unwrap();
+        assert!(!spec.contains("password_hash"));
+    }
+}
@@ -0,0 +1,161 @@
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use bson::doc;
+use image::{DynamicImage, ImageFormat};
+use mongodb::Database;
+use tokio::fs;
+use tokio::process::Command;
+use crate::config::PreviewConfig;
+use crate::error::AppError;
+use crate::extract::{epub_href, epub_package, zip_entry};
+
+// Превью лежат рядом с файлами поста в скрытом каталоге
+pub const PREVIEWS_DIR: &str = ".previews";
+
+const IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "gif", "webp"];
+
+enum Kind {
+    Image,
+    Pdf,
+    Epub,
+}
+
+fn kind(filename: &str) -> Option<Kind> {
+    let extension = Path::new(filename).extension()?.to_str()?.to_lowercase();
+    match extension.as_str() {
+        "pdf" => Some(Kind::Pdf),
+        "epub" => Some(Kind::Epub),
+        v if IMAGE_EXTENSIONS.contains(&v) => Some(Kind::Image),
+        _ => None,
+    }
+}
+
+pub fn previews_dir(post_dir: &Path) -> PathBuf {
+    post_dir.join(PREVIEWS_DIR)
+}
+
+pub fn preview_url(post_id: i64, preview: &str) -> String {
+    format!("/posts/{}/previews/{}", post_id, preview)
+}
+
+fn thumbnail(image: DynamicImage, size: u32, target: &Path) -> Result<(), AppError> {
+    image.thumbnail(size, size)
+        .to_rgb8()
+        .save_with_format(target, ImageFormat::Jpeg)
+        .map_err(|e| AppError::InternalServerError(e.to_string()))
+}
+
+fn image_thumbnail(source: &Path, size: u32, target: &Path) -> Result<(), AppError> {
+    let image = image::ImageReader::open(source)?
+        .with_guessed_format()?
+        .decode()
+        .map_err(|e| AppError::BadRequest(e.to_string()))?;
+    thumbnail(image, size, target)
+}
+
+// Обложка EPUB помечена в манифесте OPF как properties="cover-image" (EPUB 3)
+// или указана через <meta name="cover"> (EPUB 2)
+fn epub_cover(source: &Path, max_bytes: u64) -> Option<Vec<u8>> {
+    let mut archive = zip::ZipArchive::new(std::fs::File::open(source).ok()?).ok()?;
+    let (path, package) = epub_package(&mut archive, max_bytes)?;
+    let package = roxmltree::Document::parse(&package).ok()?;
+
+    let items: Vec<_> = package.descendants().filter(|v| v.has_tag_name("item")).collect();
+    let cover_id = package.descendants()
+        .find(|v| v.has_tag_name("meta") && v.attribute("name") == Some("cover"))
+        .and_then(|v| v.attribute("content"));
+    let href = items.iter()
+        .find(|v| v.attribute("properties").is_some_and(|p| p.split_whitespace().any(|p| p == "cover-image")))
+        .or_else(|| items.iter().find(|v| cover_id.is_some() && v.attribute("id") == cover_id))?
+        .attribute("href")?;
+
+    zip_entry(&mut archive, &epub_href(&path, href), max_bytes)
+}
+
+// Распакованная обложка ограничена тем же previews.max_source_bytes, что и файлы
+fn epub_thumbnail(source: &Path, size: u32, max_bytes: u64, target: &Path) -> Result<(), AppError> {
+    let cover = epub_cover(source, max_bytes).ok_or(AppError::BadRequest("epub has no cover image".into()))?;
+    let image = image::ImageReader::new(Cursor::new(cover))
+        .with_guessed_format()?
+        .decode()
+        .map_err(|e| AppError::BadRequest(e.to_string()))?;
+    thumbnail(image, size, target)
+}
+
+// Первая страница PDF рендерится внешней утилитой pdftoppm из poppler
+async fn pdf_thumbnail(config: &PreviewConfig, source: &Path, target: &Path) -> Result<(), AppError> {
+    let prefix = target.with_extension("");
+    let output = Command::new(&config.pdftoppm)
+        .args(["-f", "1", "-l", "1", "-singlefile", "-jpeg", "-scale-to"])
+        .arg(config.size.to_string())
+        .arg(source)
+        .arg(&prefix)
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err(AppError::InternalServerError(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+    }
+    Ok(())
+}
+
+// Создаёт превью для файла поста и возвращает имя файла превью,
+// либо None, если для этого типа файлов превью не делается
+#[tracing::instrument(name = "preview.generate", skip(config, post_dir))]
+pub async fn generate_preview(config: &PreviewConfig, post_dir: &Path, filename: &str) -> Result<Option<String>, AppError> {
+    let Some(kind) = kind(filename) else {
+        return Ok(None);
+    };
+
+    let source = post_dir.join(filename);
+    if fs::metadata(&source).await?.len() > config.max_source_bytes {
+        return Ok(None);
+    }
+
+    let dir = previews_dir(post_dir);
+    fs::create_dir_all(&dir).await?;
+    // Имя превью уникально, поэтому его можно кешировать бессрочно
+    let preview = format!("{}.jpg", uuid::Uuid::new_v4().simple());
+    let target = dir.join(&preview);
+
+    let size = config.size;
+    match kind {
+        Kind::Pdf => pdf_thumbnail(config, &source, &target).await?,
+        Kind::Image => {
+            let target = target.clone();
+            tokio::task::spawn_blocking(move || image_thumbnail(&source, size, &target))
+                .await
+                .map_err(|e| AppError::InternalServerError(e.to_string()))??
+        }
+        Kind::Epub => {
+            let target = target.clone();
+            let max_bytes = config.max_source_bytes;
+            tokio::task::spawn_blocking(move || epub_thumbnail(&source, size, max_bytes, &target))
+                .await
+                .map_err(|e| AppError::InternalServerError(e.to_string()))??
+        }
+    }
+
+    Ok(Some(preview))
+}
+
+// Генерирует превью для загруженных файлов в фоне и записывает их адреса в пост
+pub async fn generate_previews(config: PreviewConfig, db: Database, post_id: i64, post_dir: PathBuf, filenames: Vec<String>) {
+    let posts = db.collection::<bson::Document>("posts");
+    for filename in filenames {
+        let preview = match generate_preview(&config, &post_dir, &filename).await {
+            Ok(Some(preview)) => preview,
+            Ok(None) => continue,
+            Err(e) => {
+                tracing::warn!(post = post_id, file = %filename, error = %e, "failed to generate preview");
+                continue;
+            }
+        };
+        let update = posts.update_one(
+            doc! {"_id": post_id, "files.filename": &filename},
+            doc! {"$set": {"files.$.preview_url": preview_url(post_id, &preview)}},
+        ).await;
+        if let Err(e) = update {
+            tracing::warn!(post = post_id, file = %filename, error = %e, "failed to store preview");
+        }
+    }
+}
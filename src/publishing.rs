@@ -0,0 +1,36 @@
+use std::time::Duration;
+use bson::{doc, Document};
+use chrono::Utc;
+use mongodb::Database;
+use crate::config::PublishingConfig;
+use crate::error::AppError;
+use crate::structures::PostStatus;
+
+// Публикует отложенные посты, время которых наступило. Временем публикации
+// становится publish_at, чтобы пост занял своё место в лентах
+pub async fn publish_due(db: &Database) -> Result<u64, AppError> {
+    let filter = doc! {
+        "status": PostStatus::Scheduled,
+        "publish_at": {"$lte": bson::DateTime::from_chrono(Utc::now())},
+    };
+    let update = vec![doc! {"$set": {
+        "status": PostStatus::Published,
+        "upload_time": "$publish_at",
+        "publish_at": null,
+    }}];
+    let result = db.collection::<Document>("posts").update_many(filter, update).await?;
+    Ok(result.modified_count)
+}
+
+pub async fn run_scheduler(db: Database, config: PublishingConfig) {
+    let mut interval = tokio::time::interval(Duration::from_secs(config.interval_secs));
+
+    loop {
+        interval.tick().await;
+        match publish_due(&db).await {
+            Ok(0) => {}
+            Ok(count) => tracing::info!(count, "published scheduled posts"),
+            Err(e) => tracing::error!(error = %e, "failed to publish scheduled posts"),
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+use bson::{doc, Document};
+use mongodb::Database;
+use crate::config::QuotaConfig;
+use crate::db::find_record;
+use crate::error::AppError;
+use crate::structures::{File, Role, User};
+
+// Сколько ещё можно загрузить: квота и уже занятое место
+#[derive(Debug, Clone, Copy)]
+pub struct Allowance {
+    pub quota: u64,
+    pub used: u64,
+}
+
+impl Allowance {
+    pub fn check(&self, requested: u64) -> Result<(), AppError> {
+        if self.used.saturating_add(requested) > self.quota {
+            return Err(AppError::QuotaExceeded { used: self.used, quota: self.quota, requested });
+        }
+        Ok(())
+    }
+
+    pub fn remaining(&self) -> u64 {
+        self.quota.saturating_sub(self.used)
+    }
+}
+
+// Действующая квота пользователя; None — без ограничения или квоты выключены
+pub fn quota_for(config: &QuotaConfig, user: &User) -> Option<u64> {
+    if !config.enabled {
+        return None;
+    }
+    let quota = match user.quota_bytes {
+        Some(v) => v.max(0) as u64,
+        None => match user.role {
+            Role::User => config.user_bytes,
+            Role::Admin => config.admin_bytes,
+        },
+    };
+    (quota > 0).then_some(quota)
+}
+
+pub fn allowance(config: &QuotaConfig, user: &User) -> Option<Allowance> {
+    quota_for(config, user).map(|quota| Allowance { quota, used: user.used_bytes.max(0) as u64 })
+}
+
+// Атомарно меняет занятое место. Увеличение проверяется по квоте в том же запросе,
+// поэтому параллельные загрузки не превысят её в сумме.
+// Без with_retry: повтор $inc после потерянного ответа посчитал бы файл дважды
+pub async fn charge(db: &Database, user_id: &str, delta: i64, quota: Option<u64>) -> Result<(), AppError> {
+    let users = db.collection::<Document>("users");
+    let mut filter = doc! {"_id": user_id};
+    let checked = quota.filter(|_| delta > 0);
+    if let Some(quota) = checked {
+        filter.insert("$expr", doc! {
+            "$lte": [{"$add": [{"$ifNull": ["$used_bytes", 0_i64]}, delta]}, quota as i64],
+        });
+    }
+
+    let result = users.update_one(filter, doc! {"$inc": {"used_bytes": delta}}).await?;
+    if result.matched_count == 0 {
+        if let Some(quota) = checked {
+            let user: Option<User> = find_record(&user_id.to_string(), &db.collection("users")).await?;
+            let user = user.ok_or_else(|| AppError::not_found("user", user_id))?;
+            return Err(AppError::QuotaExceeded { used: user.used_bytes.max(0) as u64, quota, requested: delta as u64 });
+        }
+        // Пользователь удалён: освобождать место не у кого
+    }
+    Ok(())
+}
+
+// Освобождает место удалённых файлов со всеми их версиями у тех, кто их загрузил
+pub async fn release(db: &Database, files: &[File]) -> Result<(), AppError> {
+    let mut freed: HashMap<&str, i64> = HashMap::new();
+    for file in files.iter() {
+        if let Some(uploader) = file.uploader.as_deref() {
+            *freed.entry(uploader).or_default() += file.size;
+        }
+        for version in file.versions.iter() {
+            if let Some(uploader) = version.uploader.as_deref() {
+                *freed.entry(uploader).or_default() += version.size;
+            }
+        }
+    }
+    for (user, bytes) in freed {
+        charge(db, user, -bytes, None).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,147 @@
+use std::sync::Arc;
+use axum::handler::Handler;
+use axum::http::Method;
+use axum::routing::{on, MethodFilter, MethodRouter};
+use crate::AppState;
+use crate::endpoints::archive::download_post_archive;
+use crate::endpoints::feed::{follow, get_feed, unfollow};
+use crate::endpoints::files::{
+    download_post_file, get_preview, list_file_versions, list_post_files, restore_file_version, upload_files_to_post,
+};
+use crate::endpoints::health::{healthz, readyz};
+use crate::endpoints::links::{create_link, revoke_link};
+use crate::endpoints::posts::{create_post, get_posts, publish_post, rate_post, set_visibility};
+use crate::endpoints::search::search;
+use crate::endpoints::tags::{autocomplete_tags, create_aliases, delete_alias, get_tags, list_aliases};
+use crate::endpoints::user::{get_usage, login, register, set_quota, update_token};
+use crate::metrics::metrics;
+use crate::openapi::{docs, openapi_json};
+
+// Группа определяет слои, через которые проходит запрос
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Group {
+    // Изменение файлов: токен обязателен, тело ограничено limits.max_upload_bytes
+    Upload,
+    // Чтение файлов: токен необязателен, видимость поста проверяет обработчик
+    Files,
+    // Остальные маршруты с токеном
+    Authenticated,
+    // Без токена, но с ним выдача учитывает пользователя
+    Optional,
+    Public,
+}
+
+pub struct Route {
+    pub group: Group,
+    pub method: Method,
+    pub path: &'static str,
+    pub handler: MethodRouter<Arc<AppState>>,
+}
+
+fn route<H, T>(group: Group, method: Method, path: &'static str, handler: H) -> Route
+where
+    H: Handler<T, Arc<AppState>>,
+    T: 'static,
+{
+    let filter = MethodFilter::try_from(method.clone()).expect("unsupported method");
+    Route { group, method, path, handler: on(filter, handler) }
+}
+
+// Все маршруты API, кроме статической раздачи /files
+pub fn routes() -> Vec<Route> {
+    use Group::*;
+
+    vec![
+        route(Upload, Method::POST, "/posts/:post_id/upload", upload_files_to_post),
+        // Восстановление прежней версии файла
+        route(Upload, Method::POST, "/posts/:post_id/files/:filename/versions/:version/restore", restore_file_version),
+        // Подписанные ссылки на скачивание
+        route(Upload, Method::POST, "/posts/:post_id/files/:filename/links", create_link),
+        route(Upload, Method::DELETE, "/links/:link_id", revoke_link),
+
+        route(Files, Method::GET, "/posts/:post_id/files/:filename", download_post_file),
+        // История версий файла
+        route(Files, Method::GET, "/posts/:post_id/files/:filename/versions", list_file_versions),
+        route(Files, Method::GET, "/posts/:post_id/files", list_post_files),
+        // Все файлы поста одним архивом
+        route(Files, Method::GET, "/posts/:post_id/archive", download_post_archive),
+        route(Files, Method::GET, "/posts/:post_id/previews/:preview", get_preview),
+
+        route(Authenticated, Method::POST, "/create_post", create_post),
+        route(Authenticated, Method::GET, "/get_posts", get_posts),
+        route(Authenticated, Method::POST, "/rate_post", rate_post),
+        route(Authenticated, Method::PUT, "/posts/:post_id/visibility", set_visibility),
+        route(Authenticated, Method::POST, "/posts/:post_id/publish", publish_post),
+        route(Authenticated, Method::PUT, "/update_token", update_token),
+        route(Authenticated, Method::POST, "/follow", follow),
+        route(Authenticated, Method::POST, "/unfollow", unfollow),
+        route(Authenticated, Method::GET, "/feed", get_feed),
+        route(Authenticated, Method::POST, "/tags/aliases", create_aliases),
+        route(Authenticated, Method::DELETE, "/tags/aliases/:alias", delete_alias),
+        route(Authenticated, Method::GET, "/me/usage", get_usage),
+        route(Authenticated, Method::PUT, "/users/:user_id/quota", set_quota),
+
+        route(Optional, Method::GET, "/search", search),
+
+        route(Public, Method::POST, "/register", register),
+        route(Public, Method::POST, "/login", login),
+        route(Public, Method::GET, "/tags", get_tags),
+        route(Public, Method::GET, "/tags/autocomplete", autocomplete_tags),
+        route(Public, Method::GET, "/tags/aliases", list_aliases),
+        route(Public, Method::GET, "/openapi.json", openapi_json),
+        route(Public, Method::GET, "/docs", docs),
+        route(Public, Method::GET, "/metrics", metrics),
+        route(Public, Method::GET, "/healthz", healthz),
+        route(Public, Method::GET, "/readyz", readyz),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use utoipa::OpenApi;
+    use crate::openapi::ApiDoc;
+    use super::*;
+
+    // Служебные маршруты вне спецификации: сама спецификация, её страница и метрики.
+    // Статическая раздача /files в таблицу маршрутов не входит
+    const UNDOCUMENTED: &[&str] = &["/openapi.json", "/docs", "/metrics"];
+
+    // /posts/:post_id → /posts/{post_id}
+    fn openapi_path(path: &str) -> String {
+        path.split('/')
+            .map(|v| match v.strip_prefix(':') {
+                Some(name) => format!("{{{}}}", name),
+                None => v.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    #[test]
+    fn openapi_matches_router() {
+        let routed: BTreeSet<(String, String)> = routes().iter()
+            .filter(|v| !UNDOCUMENTED.contains(&v.path))
+            .map(|v| (v.method.to_string(), openapi_path(v.path)))
+            .collect();
+
+        let spec = ApiDoc::openapi();
+        let mut documented = BTreeSet::new();
+        for (path, item) in spec.paths.paths.iter() {
+            let operations = [
+                (Method::GET, &item.get),
+                (Method::POST, &item.post),
+                (Method::PUT, &item.put),
+                (Method::DELETE, &item.delete),
+                (Method::PATCH, &item.patch),
+            ];
+            for (method, operation) in operations {
+                if operation.is_some() {
+                    documented.insert((method.to_string(), path.clone()));
+                }
+            }
+        }
+
+        assert_eq!(routed, documented);
+    }
+}
@@ -1,8 +1,9 @@
 use bson::{doc, Bson};
 use chrono::{DateTime, Utc};
 use mongodb::{bson, Collection, Database};
-use serde::{Deserialize, Serialize};
-use crate::{db::{create_record, get_record, update_record}, error::AppError};
+use serde::{Deserialize, Deserializer, Serialize};
+use utoipa::ToSchema;
+use crate::{bibliography, db::{create_record, find_record, update_record}, error::AppError, tags::Taxonomy};
 
 pub struct IdGenerator {
     sequence_collection: Collection<Counter>
@@ -15,14 +16,59 @@ struct Counter {
     counter: i64,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq, ToSchema)]
 pub enum Rating {
     Up,
     Down,
     None,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Eq, PartialEq, ToSchema)]
+pub enum Role {
+    #[default]
+    User,
+    Admin,
+}
+
+// Кому доступен пост и его файлы
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, Eq, PartialEq, ToSchema)]
+pub enum Visibility {
+    // Виден всем и попадает в списки
+    #[default]
+    Public,
+    // Доступен всем по ссылке, но не попадает в списки
+    Unlisted,
+    // Только вошедшим пользователям
+    MembersOnly,
+    // Только автору и администраторам
+    Private,
+}
+
+// Стадия публикации поста. Посты, созданные до появления поля, опубликованы
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, Eq, PartialEq, ToSchema)]
+pub enum PostStatus {
+    // Черновик виден только автору
+    Draft,
+    // Будет опубликован фоновой задачей в publish_at
+    Scheduled,
+    #[default]
+    Published,
+}
+
+// Результат антивирусной проверки файла
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, Eq, PartialEq, ToSchema)]
+pub enum ScanStatus {
+    // Загружен при выключенной проверке или ещё не проверялся
+    #[default]
+    Unscanned,
+    Clean,
+    // Файл перенесён в карантин
+    Infected,
+    // clamd недоступен или вернул ошибку
+    Failed,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq, ToSchema)]
 pub struct RatedPost {
     pub post: i64,
     pub rating: Rating,
@@ -34,33 +80,78 @@ pub struct Claims {
     pub exp: i64,
 }
 
+// Запись пользователя в базе, наружу не отдаётся: в ней хранится хеш пароля,
+// поэтому и в спецификацию API она не входит
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
     #[serde(rename = "_id")]
     pub id: String,
     pub username: String,
     pub password_hash: String,
+    #[serde(default)]
+    pub role: Role,
+    #[serde(default)]
+    pub disabled: bool,
     summary: Vec<i64>,
     pub rated: Vec<RatedPost>,
+    #[serde(default)]
+    pub following: Vec<String>,
+    #[serde(default)]
+    pub followed_keywords: Vec<String>,
+    // Место, занятое загруженными файлами; меняется только через $inc
+    #[serde(default)]
+    pub used_bytes: i64,
+    // Квота, назначенная администратором: None — квота роли, 0 — без ограничения
+    #[serde(default)]
+    pub quota_bytes: Option<i64>,
+    #[serde(deserialize_with = "deserialize_date")]
     last_upload: DateTime<Utc>,
+    #[serde(deserialize_with = "deserialize_date")]
     register_date: DateTime<Utc>
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct Resource {
     #[serde(rename = "_id")]
     pub id: i64,
-    title: String,
+    pub title: String,
     description: String,
     author: String,
     author_name: String,
-    keywords: Vec<String>,
+    pub keywords: Vec<String>,
     pub files: Vec<File>,
     pub rating: i32,
+    #[serde(deserialize_with = "deserialize_date")]
     upload_time: DateTime<Utc>,
+    #[serde(default)]
+    pub hidden: bool,
+    #[serde(default)]
+    pub visibility: Visibility,
+    #[serde(default)]
+    pub status: PostStatus,
+    #[serde(default, deserialize_with = "deserialize_optional_date")]
+    pub publish_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub bibliography: Option<Bibliography>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+// Библиографическое описание издания; все поля необязательны
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct Bibliography {
+    pub authors: Vec<String>,
+    pub publisher: Option<String>,
+    pub year: Option<i32>,
+    pub edition: Option<String>,
+    // Код языка ISO 639
+    pub language: Option<String>,
+    // Хранится как ISBN-13 без дефисов
+    pub isbn: Option<String>,
+    // Хранится в нижнем регистре, без префикса https://doi.org/
+    pub doi: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct SendResource {
     #[serde(rename = "_id")]
     pub id: i64,
@@ -73,19 +164,282 @@ pub struct SendResource {
     pub rating: i32,
     upload_time: DateTime<Utc>,
     pub rate: Rating,
+    visibility: Visibility,
+    status: PostStatus,
+    publish_at: Option<DateTime<Utc>>,
+    bibliography: Option<Bibliography>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct File {
     pub(crate) filename: String,
     pub(crate) size: i64,
+    // Адрес уменьшенного изображения, появляется после фоновой генерации
+    #[serde(default)]
+    pub(crate) preview_url: Option<String>,
+    // Текст файла извлечён и доступен для поиска
+    #[serde(default)]
+    pub(crate) text_extracted: bool,
+    // Тип по сигнатуре содержимого, а не по расширению
+    #[serde(default)]
+    pub(crate) mime_type: Option<String>,
+    // SHA-256 в шестнадцатеричном виде
+    #[serde(default)]
+    pub(crate) sha256: Option<String>,
+    // id загрузившего пользователя
+    #[serde(default)]
+    pub(crate) uploader: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_optional_date")]
+    pub(crate) uploaded_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub(crate) description: Option<String>,
+    #[serde(default)]
+    pub(crate) scan_status: ScanStatus,
+    // Сигнатура для заражённых файлов или текст ошибки проверки
+    #[serde(default)]
+    pub(crate) scan_details: Option<String>,
+    // Номер текущей версии; прежние версии перечислены в versions
+    #[serde(default = "first_version")]
+    pub(crate) version: i64,
+    #[serde(default)]
+    pub(crate) versions: Vec<FileVersion>,
+}
+
+// Прежняя версия файла, хранится в каталоге .versions поста
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct FileVersion {
+    pub(crate) version: i64,
+    pub(crate) size: i64,
+    #[serde(default)]
+    pub(crate) mime_type: Option<String>,
+    #[serde(default)]
+    pub(crate) sha256: Option<String>,
+    #[serde(default)]
+    pub(crate) uploader: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_optional_date")]
+    pub(crate) uploaded_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub(crate) description: Option<String>,
+    #[serde(default)]
+    pub(crate) scan_status: ScanStatus,
+    #[serde(default)]
+    pub(crate) scan_details: Option<String>,
+}
+
+fn first_version() -> i64 {
+    1
+}
+
+impl From<&File> for FileVersion {
+    fn from(value: &File) -> Self {
+        FileVersion {
+            version: value.version,
+            size: value.size,
+            mime_type: value.mime_type.clone(),
+            sha256: value.sha256.clone(),
+            uploader: value.uploader.clone(),
+            uploaded_at: value.uploaded_at,
+            description: value.description.clone(),
+            scan_status: value.scan_status,
+            scan_details: value.scan_details.clone(),
+        }
+    }
+}
+
+impl File {
+    // Текущая версия или одна из прежних
+    pub fn find_version(&self, version: i64) -> Option<FileVersion> {
+        if version == self.version {
+            return Some(self.into());
+        }
+        self.versions.iter().find(|v| v.version == version).cloned()
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct Follow {
+    #[serde(default)]
+    pub users: Vec<String>,
+    #[serde(default)]
+    pub keywords: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TagAlias {
+    #[serde(rename = "_id")]
+    pub alias: String,
+    pub tag: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateAliases {
+    pub tag: String,
+    pub aliases: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct TagCount {
+    #[serde(rename = "_id")]
+    pub tag: String,
+    pub count: i64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Feed {
+    pub posts: Vec<SendResource>,
+    pub next_cursor: Option<FeedCursor>,
+}
+
+// Параметры запроса следующей страницы ленты
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FeedCursor {
+    pub before_time: DateTime<Utc>,
+    pub before: i64,
+}
+
+// Извлечённый текст файла, хранится отдельно от поста в коллекции file_texts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileText {
+    // "<post>/<filename>"
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub post: i64,
+    pub filename: String,
+    // Для PDF каждый элемент pages — страница
+    pub paged: bool,
+    pub pages: Vec<String>,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub extracted_at: DateTime<Utc>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TextLocation {
+    // Номер страницы (с единицы), только для PDF
+    pub page: Option<usize>,
+    pub snippet: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SearchHit {
+    pub post: i64,
+    pub title: String,
+    pub filename: String,
+    pub score: f64,
+    pub locations: Vec<TextLocation>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct CreateResource {
     title: String,
     description: String,
     keywords: Vec<String>,
+    #[serde(default)]
+    visibility: Visibility,
+    // Время отложенной публикации; без него пост остаётся черновиком до явной публикации
+    #[serde(default)]
+    publish_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    bibliography: Option<Bibliography>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SetVisibility {
+    pub visibility: Visibility,
+}
+
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct PublishPost {
+    // Время в будущем — отложенная публикация, иначе пост публикуется сразу
+    #[serde(default)]
+    pub publish_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Usage {
+    pub used_bytes: u64,
+    // None — без ограничения
+    pub quota_bytes: Option<u64>,
+    pub remaining_bytes: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SetQuota {
+    // null — квота роли, 0 — без ограничения
+    pub quota_bytes: Option<i64>,
+}
+
+// Подписанная ссылка на скачивание файла без токена
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DownloadLink {
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub post: i64,
+    pub filename: String,
+    // None — всегда текущая версия
+    pub version: Option<i64>,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub expires_at: DateTime<Utc>,
+    pub max_downloads: Option<i64>,
+    pub downloads: i64,
+    // Адрес, с которого разрешено скачивание
+    pub ip: Option<String>,
+    pub created_by: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateLink {
+    // Срок действия; по умолчанию links.default_ttl_secs
+    pub expires_in_secs: Option<u64>,
+    pub max_downloads: Option<i64>,
+    // Версия файла; по умолчанию текущая на момент скачивания
+    pub version: Option<i64>,
+    // Привязать ссылку к адресу: true — к адресу создающего запроса, или явно указанный адрес
+    #[serde(default)]
+    pub bind_ip: bool,
+    pub ip: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SignedLink {
+    pub id: String,
+    // Относительный адрес для скачивания
+    pub url: String,
+    pub expires_at: DateTime<Utc>,
+    pub max_downloads: Option<i64>,
+    pub ip: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Stats {
+    pub users: u64,
+    pub admins: u64,
+    pub disabled_users: u64,
+    pub posts: u64,
+    pub hidden_posts: u64,
+    pub files: u64,
+    pub stored_bytes: u64,
+}
+
+// Даты хранятся как BSON datetime, но старые документы содержат строки RFC3339
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredDate {
+    Native(bson::DateTime),
+    Text(DateTime<Utc>),
+}
+
+pub fn deserialize_date<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DateTime<Utc>, D::Error> {
+    Ok(match StoredDate::deserialize(deserializer)? {
+        StoredDate::Native(v) => v.to_chrono(),
+        StoredDate::Text(v) => v,
+    })
+}
+
+pub fn deserialize_optional_date<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error> {
+    Ok(Option::<StoredDate>::deserialize(deserializer)?.map(|v| match v {
+        StoredDate::Native(v) => v.to_chrono(),
+        StoredDate::Text(v) => v,
+    }))
 }
 
 impl IdGenerator {
@@ -95,12 +449,9 @@ impl IdGenerator {
         }
     }
     pub async fn get_id(&self, count_id: String) -> Result<i64, AppError> {
-        let mut counter = match get_record(&count_id, &self.sequence_collection).await {
-            Ok(v) => v,
-            Err(e) => {
-                if let AppError::InternalServerError = e {
-                    return Err(e);
-                }
+        let mut counter = match find_record(&count_id, &self.sequence_collection).await? {
+            Some(v) => v,
+            None => {
                 let counter = Counter {id: count_id, counter: 0};
                 create_record(&counter, &self.sequence_collection).await?;
                 counter
@@ -115,12 +466,19 @@ impl IdGenerator {
 }
 
 impl CreateResource {
+    pub fn new(title: String, description: String, keywords: Vec<String>) -> Self {
+        CreateResource { title, description, keywords, visibility: Visibility::Public, publish_at: None, bibliography: None }
+    }
+
     pub async fn into_resource(
         mut self,
         author: String,
         author_name: String,
         id_gen: &IdGenerator,
+        taxonomy: &Taxonomy,
     ) -> Result<Resource, AppError> {
+        self.keywords = taxonomy.canonicalize(self.keywords).await?;
+        let bibliography = self.bibliography.map(bibliography::normalize).transpose()?;
         Ok(Resource {
             id: id_gen.get_id("post".into()).await?,
             title: self.title,
@@ -131,11 +489,31 @@ impl CreateResource {
             files: vec![],
             rating: 0,
             upload_time: Utc::now(),
+            hidden: false,
+            visibility: self.visibility,
+            status: if self.publish_at.is_some() { PostStatus::Scheduled } else { PostStatus::Draft },
+            publish_at: self.publish_at,
+            bibliography,
         })
     }
 }
 
 impl Resource {
+    pub fn author(&self) -> &str {
+        &self.author
+    }
+
+    pub fn upload_time(&self) -> DateTime<Utc> {
+        self.upload_time
+    }
+
+    // Публикует пост сейчас: в лентах посты упорядочены по времени публикации
+    pub fn publish(&mut self) {
+        self.status = PostStatus::Published;
+        self.publish_at = None;
+        self.upload_time = Utc::now();
+    }
+
     pub fn into_send_resource(self, rating: Rating) -> SendResource {
         SendResource {
             id: self.id,
@@ -148,6 +526,10 @@ impl Resource {
             rating: self.rating,
             upload_time: self.upload_time,
             rate: rating,
+            visibility: self.visibility,
+            status: self.status,
+            publish_at: self.publish_at,
+            bibliography: self.bibliography,
         }
     }
 } 
@@ -158,8 +540,14 @@ impl User {
             id,
             username,
             password_hash,
+            role: Role::User,
+            disabled: false,
             summary: vec![],
             rated: vec![],
+            following: vec![],
+            followed_keywords: vec![],
+            used_bytes: 0,
+            quota_bytes: None,
             last_upload: Utc::now(),
             register_date: Utc::now(),
         }
@@ -181,9 +569,38 @@ impl User {
             Rating::None => {}
         }
         self.rated.push(rated_post);
-        println!("{}", rating);
+        tracing::debug!(post = self.rated.last().map(|v| v.post), delta = rating, "post rated");
         rating
     }
+
+    pub fn is_admin(&self) -> bool {
+        self.role == Role::Admin
+    }
+
+    pub fn get_rating(&self, post: i64) -> Rating {
+        match self.rated.iter().find(|v| v.post == post) {
+            Some(v) => v.rating.clone(),
+            None => Rating::None,
+        }
+    }
+
+    pub fn follow(&mut self, follow: Follow) {
+        for user in follow.users {
+            if user != self.id && !self.following.contains(&user) {
+                self.following.push(user);
+            }
+        }
+        for keyword in follow.keywords {
+            if !self.followed_keywords.contains(&keyword) {
+                self.followed_keywords.push(keyword);
+            }
+        }
+    }
+
+    pub fn unfollow(&mut self, follow: Follow) {
+        self.following.retain(|v| !follow.users.contains(v));
+        self.followed_keywords.retain(|v| !follow.keywords.contains(v));
+    }
 }
 
 impl From<User> for Bson {
@@ -192,10 +609,19 @@ impl From<User> for Bson {
             "_id": user.id,
             "username": user.username,
             "password_hash": user.password_hash,
+            "role": match user.role {
+                Role::User => "User",
+                Role::Admin => "Admin",
+            },
+            "disabled": user.disabled,
             "summary": user.summary,
             "rated": user.rated,
-            "last_upload": user.last_upload.to_rfc3339(),
-            "register_date": user.register_date.to_rfc3339(),
+            "following": user.following,
+            "followed_keywords": user.followed_keywords,
+            // used_bytes не пишется: update_record не должен затирать параллельные $inc
+            "quota_bytes": user.quota_bytes,
+            "last_upload": bson::DateTime::from_chrono(user.last_upload),
+            "register_date": bson::DateTime::from_chrono(user.register_date),
         })
     }
 }
@@ -211,16 +637,94 @@ impl From<Resource> for Bson {
             "keywords": value.keywords,
             "files": value.files,
             "rating": value.rating,
-            "upload_time": value.upload_time.to_rfc3339(),
+            "upload_time": bson::DateTime::from_chrono(value.upload_time),
+            "hidden": value.hidden,
+            "visibility": value.visibility,
+            "status": value.status,
+            "publish_at": value.publish_at.map(bson::DateTime::from_chrono),
+            "bibliography": value.bibliography,
+        })
+    }
+}
+
+impl From<Bibliography> for Bson {
+    fn from(value: Bibliography) -> Self {
+        Bson::Document(doc! {
+            "authors": value.authors,
+            "publisher": value.publisher,
+            "year": value.year,
+            "edition": value.edition,
+            "language": value.language,
+            "isbn": value.isbn,
+            "doi": value.doi,
         })
     }
 }
 
+impl From<Visibility> for Bson {
+    fn from(value: Visibility) -> Self {
+        Bson::String(match value {
+            Visibility::Public => "Public",
+            Visibility::Unlisted => "Unlisted",
+            Visibility::MembersOnly => "MembersOnly",
+            Visibility::Private => "Private",
+        }.into())
+    }
+}
+
+impl From<PostStatus> for Bson {
+    fn from(value: PostStatus) -> Self {
+        Bson::String(match value {
+            PostStatus::Draft => "Draft",
+            PostStatus::Scheduled => "Scheduled",
+            PostStatus::Published => "Published",
+        }.into())
+    }
+}
+
+impl From<ScanStatus> for Bson {
+    fn from(value: ScanStatus) -> Self {
+        Bson::String(match value {
+            ScanStatus::Unscanned => "Unscanned",
+            ScanStatus::Clean => "Clean",
+            ScanStatus::Infected => "Infected",
+            ScanStatus::Failed => "Failed",
+        }.into())
+    }
+}
+
 impl From<File> for Bson {
     fn from(value: File) -> Self {
         Bson::Document(doc! {
             "filename": value.filename,
             "size": value.size,
+            "preview_url": value.preview_url,
+            "text_extracted": value.text_extracted,
+            "mime_type": value.mime_type,
+            "sha256": value.sha256,
+            "uploader": value.uploader,
+            "uploaded_at": value.uploaded_at.map(bson::DateTime::from_chrono),
+            "description": value.description,
+            "scan_status": value.scan_status,
+            "scan_details": value.scan_details,
+            "version": value.version,
+            "versions": value.versions,
+        })
+    }
+}
+
+impl From<FileVersion> for Bson {
+    fn from(value: FileVersion) -> Self {
+        Bson::Document(doc! {
+            "version": value.version,
+            "size": value.size,
+            "mime_type": value.mime_type,
+            "sha256": value.sha256,
+            "uploader": value.uploader,
+            "uploaded_at": value.uploaded_at.map(bson::DateTime::from_chrono),
+            "description": value.description,
+            "scan_status": value.scan_status,
+            "scan_details": value.scan_details,
         })
     }
 }
@@ -234,6 +738,15 @@ impl From<Counter> for Bson {
     }
 }
 
+impl From<TagAlias> for Bson {
+    fn from(value: TagAlias) -> Self {
+        Bson::Document(doc! {
+            "_id": value.alias,
+            "tag": value.tag,
+        })
+    }
+}
+
 impl From<RatedPost> for Bson {
     fn from(value: RatedPost) -> Self {
         Bson::Document(doc! {
@@ -0,0 +1,97 @@
+use mongodb::{Collection, Database};
+use unicode_normalization::UnicodeNormalization;
+use crate::db::find_record;
+use crate::error::AppError;
+use crate::structures::TagAlias;
+
+pub struct Taxonomy {
+    alias_collection: Collection<TagAlias>,
+}
+
+// Приводим ключевое слово к единому виду: NFKC, нижний регистр, одиночные пробелы
+pub fn normalize_keyword(keyword: &str) -> String {
+    keyword.nfkc()
+        .collect::<String>()
+        .to_lowercase()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub fn escape_regex(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if "\\^$.|?*+()[]{}".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+impl Taxonomy {
+    pub fn new(db: &Database) -> Taxonomy {
+        Taxonomy {
+            alias_collection: db.collection("tag_aliases"),
+        }
+    }
+
+    pub fn aliases(&self) -> &Collection<TagAlias> {
+        &self.alias_collection
+    }
+
+    pub async fn resolve(&self, keyword: &str) -> Result<Option<String>, AppError> {
+        let keyword = normalize_keyword(keyword);
+        if keyword.is_empty() {
+            return Ok(None);
+        }
+        match find_record(&keyword, &self.alias_collection).await? {
+            Some(alias) => Ok(Some(alias.tag)),
+            None => Ok(Some(keyword)),
+        }
+    }
+
+    pub async fn canonicalize(&self, keywords: Vec<String>) -> Result<Vec<String>, AppError> {
+        let mut result: Vec<String> = vec![];
+        for keyword in keywords {
+            if let Some(tag) = self.resolve(&keyword).await? {
+                if !result.contains(&tag) {
+                    result.push(tag);
+                }
+            }
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keyword_case() {
+        assert_eq!(normalize_keyword("Rust"), "rust");
+        assert_eq!(normalize_keyword("МАШИННОЕ Обучение"), "машинное обучение");
+        assert_eq!(normalize_keyword("ΣΟΦΙΑ"), "σοφια");
+    }
+
+    #[test]
+    fn keyword_whitespace() {
+        assert_eq!(normalize_keyword("  machine   learning "), "machine learning");
+        assert_eq!(normalize_keyword("machine\tlearning\n"), "machine learning");
+        // Неразрывный и полноширинный пробелы тоже разделяют слова
+        assert_eq!(normalize_keyword("machine\u{a0}learning"), "machine learning");
+        assert_eq!(normalize_keyword("machine\u{3000}learning"), "machine learning");
+        assert_eq!(normalize_keyword("   "), "");
+    }
+
+    #[test]
+    fn keyword_unicode() {
+        // Полноширинные буквы, лигатуры и составные символы приводятся к одному виду
+        assert_eq!(normalize_keyword("ＲＵＳＴ"), "rust");
+        assert_eq!(normalize_keyword("ﬁle"), "file");
+        assert_eq!(normalize_keyword("cafe\u{301}"), normalize_keyword("caf\u{e9}"));
+        assert_eq!(normalize_keyword("Е\u{308}ж"), "ёж");
+        assert_eq!(normalize_keyword("x²"), "x2");
+    }
+}
@@ -0,0 +1,62 @@
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use axum::extract::Request;
+use axum::http::header::HOST;
+use axum::http::uri::Authority;
+use axum::http::StatusCode;
+use axum::response::Redirect;
+use axum::Router;
+use axum_server::tls_rustls::RustlsConfig;
+use tokio::fs;
+use crate::config::TlsConfig;
+
+pub async fn load_config(config: &TlsConfig) -> std::io::Result<RustlsConfig> {
+    // Провайдер ставится один раз на процесс, повторная установка не ошибка
+    let _ = rustls::crypto::ring::default_provider().install_default();
+    RustlsConfig::from_pem_file(&config.cert, &config.key).await
+}
+
+async fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).await.and_then(|v| v.modified()).ok()
+}
+
+// Периодически сверяем время изменения сертификата и ключа и перечитываем их при изменении.
+// Если новые файлы не читаются, остаётся прежний сертификат
+pub async fn watch_certificates(rustls: RustlsConfig, config: TlsConfig) {
+    let mut interval = tokio::time::interval(Duration::from_secs(config.reload_interval_secs));
+    interval.tick().await;
+    let mut last = (modified(&config.cert).await, modified(&config.key).await);
+
+    loop {
+        interval.tick().await;
+        let current = (modified(&config.cert).await, modified(&config.key).await);
+        if current == last {
+            continue;
+        }
+        match rustls.reload_from_pem_file(&config.cert, &config.key).await {
+            Ok(()) => {
+                tracing::info!(cert = %config.cert.display(), "reloaded TLS certificate");
+                last = current;
+            }
+            Err(e) => tracing::error!(cert = %config.cert.display(), error = %e, "failed to reload TLS certificate"),
+        }
+    }
+}
+
+// Отвечает на любой HTTP-запрос постоянным перенаправлением на тот же путь по HTTPS
+pub fn redirect_app(https_port: u16) -> Router {
+    Router::new().fallback(move |request: Request| async move {
+        let host = request.headers()
+            .get(HOST)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<Authority>().ok())
+            .ok_or(StatusCode::BAD_REQUEST)?;
+        let path = request.uri().path_and_query().map(|v| v.as_str()).unwrap_or("/");
+        let location = if https_port == 443 {
+            format!("https://{}{}", host.host(), path)
+        } else {
+            format!("https://{}:{}{}", host.host(), https_port, path)
+        };
+        Ok::<_, StatusCode>(Redirect::permanent(&location))
+    })
+}
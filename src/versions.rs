@@ -0,0 +1,57 @@
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+// Прежние версии лежат рядом с файлами поста: .versions/<имя файла>/<номер версии>
+pub const VERSIONS_DIR: &str = ".versions";
+// Временные файлы незавершённых загрузок: .upload-<uuid> в каталоге поста
+pub const PART_PREFIX: &str = ".upload-";
+
+pub fn versions_dir(post_dir: &Path) -> PathBuf {
+    post_dir.join(VERSIONS_DIR)
+}
+
+pub fn version_path(post_dir: &Path, filename: &str, version: i64) -> PathBuf {
+    versions_dir(post_dir).join(filename).join(version.to_string())
+}
+
+pub fn part_path(post_dir: &Path) -> PathBuf {
+    post_dir.join(format!("{}{}", PART_PREFIX, uuid::Uuid::new_v4().simple()))
+}
+
+// Путь к содержимому версии: текущая лежит под своим именем, прежние — в .versions
+pub fn content_path(post_dir: &Path, filename: &str, current: i64, version: i64) -> PathBuf {
+    if version == current {
+        post_dir.join(filename)
+    } else {
+        version_path(post_dir, filename, version)
+    }
+}
+
+// Переносит текущую версию файла в .versions перед заменой новой.
+// Отсутствие файла на диске (например, после карантина) не ошибка
+pub async fn keep_version(post_dir: &Path, filename: &str, version: i64) -> std::io::Result<()> {
+    let target = version_path(post_dir, filename, version);
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    match fs::rename(post_dir.join(filename), &target).await {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+// Делает part новой текущей версией; прежняя текущая (если есть) уходит в .versions
+pub async fn replace_current(post_dir: &Path, part: &Path, filename: &str, previous: Option<i64>) -> std::io::Result<()> {
+    let current = post_dir.join(filename);
+    if let Some(version) = previous {
+        keep_version(post_dir, filename, version).await?;
+    }
+    if let Err(e) = fs::rename(part, &current).await {
+        // Возвращаем прежнюю версию на место
+        if let Some(version) = previous {
+            let _ = fs::rename(version_path(post_dir, filename, version), &current).await;
+        }
+        return Err(e);
+    }
+    Ok(())
+}